@@ -0,0 +1,532 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::{Context, Error, Kind, Result};
+use toml_edit::{DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+pub const CRATES_IO: &str = "crates-io";
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+fn read_toml(path: &Path) -> Option<toml::Table> {
+    toml::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+/// Every package manifest under `root`, skipping git metadata and build output.
+pub fn packages(root: &Path) -> Vec<(String, PathBuf)> {
+    let mut found = Vec::new();
+
+    if let Some(name) = read_toml(&root.join("Cargo.toml")).and_then(|manifest| {
+        manifest
+            .get("package")?
+            .get("name")?
+            .as_str()
+            .map(|name| name.to_string())
+    }) {
+        found.push((name, root.to_path_buf()));
+    }
+
+    for entry in std::fs::read_dir(root).into_iter().flatten().flatten() {
+        if entry.path().is_dir() && !matches!(entry.file_name().to_str(), Some(".git" | "target")) {
+            found.extend(packages(&entry.path()));
+        }
+    }
+
+    found
+}
+
+/// The workspace root containing `from`, or the closest package if it isn't in a workspace.
+pub fn project_root(from: &Path) -> Option<PathBuf> {
+    let mut manifests = from
+        .ancestors()
+        .filter(|dir| dir.join("Cargo.toml").is_file());
+    let closest = manifests.next()?;
+
+    std::iter::once(closest)
+        .chain(manifests)
+        .find(|dir| read_toml(&dir.join("Cargo.toml")).is_some_and(|m| m.contains_key("workspace")))
+        .unwrap_or(closest)
+        .to_path_buf()
+        .into()
+}
+
+fn lock_source(source: &str) -> Option<String> {
+    match source.strip_prefix("git+") {
+        Some(url) => Some(url.split(['?', '#']).next()?.to_string()),
+        None if source.contains("github.com/rust-lang/crates.io-index")
+            || source.contains("index.crates.io") =>
+        {
+            Some(CRATES_IO.to_string())
+        }
+        None => None,
+    }
+}
+
+fn manifest_sources(table: &toml::Table, sources: &mut BTreeMap<String, BTreeSet<String>>) {
+    for (key, dep) in DEPENDENCY_TABLES
+        .iter()
+        .filter_map(|name| table.get(*name)?.as_table())
+        .flatten()
+    {
+        let (name, source) = match dep {
+            toml::Value::String(_) => (key.as_str(), CRATES_IO),
+            toml::Value::Table(dep) if dep.contains_key("path") || dep.contains_key("registry") => {
+                continue
+            }
+            toml::Value::Table(dep) => (
+                dep.get("package").and_then(|p| p.as_str()).unwrap_or(key),
+                dep.get("git").and_then(|g| g.as_str()).unwrap_or(CRATES_IO),
+            ),
+            _ => continue,
+        };
+
+        sources
+            .entry(name.to_string())
+            .or_default()
+            .insert(source.to_string());
+    }
+}
+
+/// Maps each package in the project's dependency graph to the sources it's pulled from.
+pub fn sources(root: &Path) -> BTreeMap<String, BTreeSet<String>> {
+    let mut sources = BTreeMap::<String, BTreeSet<String>>::new();
+
+    if let Some(lock) = read_toml(&root.join("Cargo.lock")) {
+        for package in lock
+            .get("package")
+            .and_then(|p| p.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let (Some(name), Some(source)) = (
+                package.get("name").and_then(|n| n.as_str()),
+                package
+                    .get("source")
+                    .and_then(|s| s.as_str())
+                    .and_then(lock_source),
+            ) else {
+                continue;
+            };
+
+            sources.entry(name.to_string()).or_default().insert(source);
+        }
+    }
+
+    if let Some(manifest) = read_toml(&root.join("Cargo.toml")) {
+        manifest_sources(&manifest, &mut sources);
+        if let Some(workspace) = manifest.get("workspace").and_then(|w| w.as_table()) {
+            manifest_sources(workspace, &mut sources);
+        }
+    }
+
+    sources
+}
+
+/// Writes `[patch.<source>]` entries into `manifest`, replacing existing entries for the same crates.
+pub fn write_patches(
+    manifest: &Path,
+    root: &Path,
+    patches: &[(String, String, PathBuf)],
+) -> Result<()> {
+    let mut document = match manifest.exists() {
+        true => std::fs::read_to_string(manifest)
+            .context(
+                Kind::Filesystem,
+                format!("Can't read {}", manifest.display()),
+            )?
+            .parse::<DocumentMut>()
+            .context(Kind::Config, format!("Can't parse {}", manifest.display()))?,
+        false => DocumentMut::new(),
+    };
+
+    let patch = document
+        .entry("patch")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_mut()
+        .context(Kind::Config, "`patch` isn't a table")?;
+
+    for (source, name, path) in patches {
+        let path = path.strip_prefix(root).unwrap_or(path);
+        let mut entry = InlineTable::new();
+        entry.insert("path", path.to_string_lossy().as_ref().into());
+
+        patch
+            .entry(source)
+            .or_insert_with(|| Item::Table(Table::new()))
+            .as_table_mut()
+            .context(Kind::Config, format!("`patch.{source}` isn't a table"))?
+            .insert(name, Item::Value(entry.into()));
+    }
+
+    if let Some(parent) = manifest.parent() {
+        std::fs::create_dir_all(parent).context(
+            Kind::Filesystem,
+            format!("Can't create {}", parent.display()),
+        )?;
+    }
+    std::fs::write(manifest, document.to_string()).context(
+        Kind::Filesystem,
+        format!("Can't write to {}", manifest.display()),
+    )
+}
+
+fn package_files(dir: &Path, root: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).context(Kind::Filesystem, format!("Can't read {}", dir.display()))?
+    {
+        let path = entry
+            .context(Kind::Filesystem, format!("Can't read {}", dir.display()))?
+            .path();
+
+        if path.is_dir() {
+            // Nested packages are exported on their own
+            if !matches!(
+                path.file_name().and_then(|n| n.to_str()),
+                Some(".git" | "target")
+            ) && !path.join("Cargo.toml").exists()
+            {
+                package_files(&path, root, files)?;
+            }
+        } else if path.is_file() {
+            files.push(path.strip_prefix(root).unwrap().to_path_buf());
+        }
+    }
+
+    Ok(())
+}
+
+/// The root and `[workspace]` table of the workspace `package` belongs to, looking no further up
+/// than `clone`.
+fn workspace(package: &Path, clone: &Path) -> Option<(PathBuf, Table)> {
+    package
+        .ancestors()
+        .take_while(|dir| dir.starts_with(clone))
+        .find_map(|dir| {
+            let document = std::fs::read_to_string(dir.join("Cargo.toml"))
+                .ok()?
+                .parse::<DocumentMut>()
+                .ok()?;
+            Some((
+                dir.to_path_buf(),
+                document.get("workspace")?.as_table()?.clone(),
+            ))
+        })
+}
+
+/// Whether a manifest entry is `{ workspace = true }`.
+fn inherits(item: &Item) -> bool {
+    item.get("workspace").and_then(Item::as_bool) == Some(true)
+}
+
+/// The manifest of the package at `package` as `cargo package` would publish it: fields,
+/// dependencies and lints inherited from the workspace are filled in, path dependencies become
+/// version requirements and `[workspace]` goes. Also returns the files from outside the package
+/// the manifest now names, by their name in the package.
+fn normalize(package: &Path, clone: &Path) -> Result<(String, Vec<(String, PathBuf)>)> {
+    let path = package.join("Cargo.toml");
+    let mut manifest = std::fs::read_to_string(&path)
+        .context(Kind::Filesystem, format!("Can't read {}", path.display()))?
+        .parse::<DocumentMut>()
+        .context(Kind::Config, format!("Can't parse {}", path.display()))?;
+    let workspace = workspace(package, clone);
+    let inherited = |what: &str| {
+        workspace.as_ref().context(
+            Kind::Config,
+            format!(
+                "{} inherits {what} but isn't in a workspace",
+                path.display()
+            ),
+        )
+    };
+    let mut outside = Vec::new();
+
+    manifest.remove("workspace");
+
+    if let Some(fields) = manifest
+        .get_mut("package")
+        .and_then(Item::as_table_like_mut)
+    {
+        for (key, item) in fields.iter_mut().filter(|(_, item)| inherits(item)) {
+            let (root, table) = inherited(&format!("`package.{}`", key.get()))?;
+            let mut value = table
+                .get("package")
+                .and_then(|fields| fields.get(key.get()))
+                .and_then(Item::as_value)
+                .cloned()
+                .context(
+                    Kind::Config,
+                    format!("The workspace doesn't define `package.{}`", key.get()),
+                )?;
+
+            // Files are named relative to the workspace root, which isn't exported
+            if let ("readme" | "license-file", Some(file)) = (key.get(), value.as_str()) {
+                let file = root.join(file);
+                value = match file.strip_prefix(package) {
+                    Ok(inside) => inside.to_string_lossy().as_ref().into(),
+                    Err(_) => {
+                        let name = file
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_string())
+                            .context(Kind::Config, format!("Invalid {} path", key.get()))?;
+                        outside.push((name.clone(), file));
+                        name.into()
+                    }
+                };
+            }
+
+            value.decor_mut().clear();
+            *item = Item::Value(value);
+        }
+    }
+
+    if let Some(lints) = manifest.get_mut("lints").filter(|lints| inherits(lints)) {
+        *lints = inherited("`lints`")?
+            .1
+            .get("lints")
+            .cloned()
+            .context(Kind::Config, "The workspace doesn't define `lints`")?;
+    }
+
+    normalize_dependencies(manifest.as_table_mut(), package, workspace.as_ref(), clone)?;
+    if let Some(targets) = manifest.get_mut("target").and_then(Item::as_table_like_mut) {
+        for (_, target) in targets.iter_mut() {
+            if let Some(target) = target.as_table_like_mut() {
+                normalize_dependencies(target, package, workspace.as_ref(), clone)?;
+            }
+        }
+    }
+
+    Ok((manifest.to_string(), outside))
+}
+
+/// Fills in inherited dependencies and swaps `path` for `version` in each dependency table of
+/// `parent`. Path dev-dependencies without a version are dropped, as `cargo package` does.
+fn normalize_dependencies(
+    parent: &mut dyn TableLike,
+    package: &Path,
+    workspace: Option<&(PathBuf, Table)>,
+    clone: &Path,
+) -> Result<()> {
+    for kind in DEPENDENCY_TABLES {
+        let Some(table) = parent.get_mut(kind).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+
+        let names = table
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        for name in names {
+            let item = table.get(&name).unwrap();
+            let Some(entry) = item.as_table_like() else {
+                continue;
+            };
+
+            let mut merged = InlineTable::new();
+            let mut base = package.to_path_buf();
+            if inherits(item) {
+                let (root, workspace) = workspace.context(
+                    Kind::Config,
+                    format!("Dependency {name} is inherited but the package isn't in a workspace"),
+                )?;
+                let inherited = workspace
+                    .get("dependencies")
+                    .and_then(|dependencies| dependencies.get(&name))
+                    .context(
+                        Kind::Config,
+                        format!("The workspace doesn't define dependency {name}"),
+                    )?;
+                match inherited.as_table_like() {
+                    Some(inherited) => {
+                        for (key, value) in inherited.iter() {
+                            if let Some(value) = value.as_value() {
+                                merged.insert(key, value.clone());
+                            }
+                        }
+                    }
+                    None => {
+                        if let Some(version) = inherited.as_value() {
+                            merged.insert("version", version.clone());
+                        }
+                    }
+                }
+                base = root.clone();
+            } else if !entry.contains_key("path") {
+                continue;
+            }
+
+            for (key, value) in entry.iter().filter(|(key, _)| *key != "workspace") {
+                let Some(value) = value.as_value() else {
+                    continue;
+                };
+                match (
+                    key,
+                    merged.get_mut("features").and_then(Value::as_array_mut),
+                ) {
+                    // Features add to the workspace's
+                    ("features", Some(features)) => {
+                        for feature in value.as_array().into_iter().flatten() {
+                            if !features.iter().any(|f| f.as_str() == feature.as_str()) {
+                                features.push(feature.clone());
+                            }
+                        }
+                    }
+                    _ => {
+                        merged.insert(key, value.clone());
+                    }
+                }
+            }
+
+            if let Some(path) = merged.remove("path") {
+                let path = base.join(path.as_str().context(
+                    Kind::Config,
+                    format!("Path of dependency {name} isn't a string"),
+                )?);
+                if !merged.contains_key("version") {
+                    match package_version(&path, clone) {
+                        Some(version) => {
+                            merged.insert("version", version.into());
+                        }
+                        None if kind == "dev-dependencies" => {
+                            table.remove(&name);
+                            continue;
+                        }
+                        None => {
+                            return Err(Error::new(
+                                Kind::Config,
+                                format!("Path dependency {name} has no version"),
+                            ))
+                        }
+                    }
+                }
+            }
+            merged.fmt();
+
+            let item = table.get_mut(&name).unwrap();
+            *item = match item {
+                // `name.workspace = true` stays a single line
+                Item::Table(table) if !table.is_dotted() => Item::Table(merged.into_table()),
+                Item::Table(_) => Item::Value(Value::InlineTable(merged)),
+                Item::Value(value) => {
+                    let decor = value.decor().clone();
+                    let mut value = Value::InlineTable(merged);
+                    *value.decor_mut() = decor;
+                    Item::Value(value)
+                }
+                _ => continue,
+            };
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies the package at `source`, found in the checkout at `clone`, into `target` alongside the
+/// `.cargo-checksum.json` cargo expects.
+///
+/// The manifest is normalized so the package builds outside its workspace, keeping the original as
+/// `Cargo.toml.orig`. There's no `.crate` file to take a package checksum of, so a Cargo.lock
+/// holding one for the same crates.io version won't accept the export (see `checksummed`).
+pub fn export(source: &Path, clone: &Path, target: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    package_files(source, source, &mut files)?;
+    let (manifest, outside) = normalize(source, clone)?;
+
+    if target.exists() {
+        std::fs::remove_dir_all(target).context(
+            Kind::Filesystem,
+            format!("Can't remove {}", target.display()),
+        )?;
+    }
+
+    let mut contents = BTreeMap::<PathBuf, Vec<u8>>::new();
+    for file in files {
+        let text = std::fs::read(source.join(&file)).context(
+            Kind::Filesystem,
+            format!("Can't read {}", source.join(&file).display()),
+        )?;
+        contents.insert(file, text);
+    }
+    if let Some(original) = contents.insert("Cargo.toml".into(), manifest.into_bytes()) {
+        contents.insert("Cargo.toml.orig".into(), original);
+    }
+    for (name, path) in outside {
+        let text = std::fs::read(&path)
+            .context(Kind::Filesystem, format!("Can't read {}", path.display()))?;
+        contents.entry(name.into()).or_insert(text);
+    }
+
+    let mut checksums = BTreeMap::<String, String>::new();
+    for (file, contents) in contents {
+        let destination = target.join(&file);
+
+        std::fs::create_dir_all(destination.parent().unwrap()).context(
+            Kind::Filesystem,
+            format!("Can't create {}", destination.display()),
+        )?;
+        std::fs::write(&destination, &contents).context(
+            Kind::Filesystem,
+            format!("Can't write to {}", destination.display()),
+        )?;
+
+        checksums.insert(
+            file.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            format!("{:x}", Sha256::digest(&contents)),
+        );
+    }
+
+    std::fs::write(
+        target.join(".cargo-checksum.json"),
+        serde_json::json!({ "files": checksums, "package": null }).to_string(),
+    )
+    .context(
+        Kind::Filesystem,
+        format!("Can't write checksums for {}", target.display()),
+    )
+}
+
+/// The version of the package at `package`, taken from the workspace it's in within `clone` if
+/// it inherits it.
+pub fn package_version(package: &Path, clone: &Path) -> Option<String> {
+    let manifest = read_toml(&package.join("Cargo.toml"))?;
+    let version = manifest.get("package")?.get("version")?;
+
+    match version.get("workspace").and_then(|w| w.as_bool()) {
+        Some(true) => workspace(package, clone)?
+            .1
+            .get("package")?
+            .get("version")?
+            .as_str()
+            .map(|version| version.to_string()),
+        _ => version.as_str().map(|version| version.to_string()),
+    }
+}
+
+/// Crates the project's Cargo.lock holds a crates.io checksum for, by name and version.
+///
+/// Cargo checks these against the package checksum of a directory source, which an export
+/// doesn't have, so the lockfile entries have to go for the export to replace them.
+pub fn checksummed(root: &Path) -> BTreeSet<(String, String)> {
+    read_toml(&root.join("Cargo.lock"))
+        .and_then(|lock| lock.get("package")?.as_array().cloned())
+        .into_iter()
+        .flatten()
+        .filter(|package| package.get("checksum").is_some())
+        .filter_map(|package| {
+            Some((
+                package.get("name")?.as_str()?.to_string(),
+                package.get("version")?.as_str()?.to_string(),
+            ))
+        })
+        .collect()
+}
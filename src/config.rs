@@ -0,0 +1,383 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use git2::Repository;
+use serde::Deserialize;
+use toml_edit::{DocumentMut, Item, TableLike, Value};
+
+use crate::{
+    error::{Context, Error, Kind, Result},
+    git, lock,
+    types::{Config, Dependency, CONFIG_VERSION},
+    workspace,
+};
+
+/// Externally tagged dependencies written before `0.2.0`, holding the clone's `.git` path.
+#[derive(Deserialize, Debug, Clone)]
+enum LegacyDependency {
+    Dep(String),
+    DepWithHash(String, String),
+    Tag(String, String),
+    Rev(String, String),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct LegacyConfig {
+    dependencies: BTreeMap<String, LegacyDependency>,
+}
+
+pub fn empty() -> Config {
+    Config {
+        version: CONFIG_VERSION.to_string(),
+        dependencies: Default::default(),
+        auth: Default::default(),
+    }
+}
+
+pub fn read(home: &Path) -> Result<Config> {
+    let path = home.join(workspace::CONFIG);
+    if !path.exists() {
+        return Err(Error::new(
+            Kind::Config,
+            format!("No .vendman directory found at {}", home.display()),
+        )
+        .hint("Run `vendman init` to initialize it"));
+    }
+
+    let text =
+        std::fs::read_to_string(&path).context(Kind::Filesystem, "Can't read config file")?;
+
+    // Older configs are only migrated in memory here, `upgrade` writes them back
+    match version(&text)?.as_deref() {
+        Some(CONFIG_VERSION) => {
+            toml::from_str(&text).context(Kind::Config, "Can't parse config file")
+        }
+        Some("0.1.0") => migrate(home, &text),
+        version => Err(Error::new(
+            Kind::Config,
+            format!(
+                "Unsupported config version {}",
+                version.unwrap_or("(missing)")
+            ),
+        )),
+    }
+}
+
+/// Rewrites a config file from an older version in the current format, for commands that
+/// change the home anyway.
+pub fn upgrade(home: &Path) -> Result<()> {
+    let path = home.join(workspace::CONFIG);
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let text =
+        std::fs::read_to_string(&path).context(Kind::Filesystem, "Can't read config file")?;
+    match version(&text)?.as_deref() {
+        Some("0.1.0") => write(home, &migrate(home, &text)?),
+        _ => Ok(()),
+    }
+}
+
+fn version(text: &str) -> Result<Option<String>> {
+    let document = text
+        .parse::<toml::Table>()
+        .context(Kind::Config, "Can't parse config file")?;
+    Ok(document
+        .get("version")
+        .and_then(|v| v.as_str())
+        .map(str::to_string))
+}
+
+/// Writes `config` into the config file, leaving the comments and formatting of whatever didn't
+/// change as they were.
+pub fn write(home: &Path, config: &Config) -> Result<()> {
+    workspace::replace(&home.join(workspace::CONFIG), render(home, config)?)
+        .context(Kind::Filesystem, "Can't write to config file")
+}
+
+/// Line diff of the config file against how `write` would leave it, for `--dry-run`.
+pub fn preview(home: &Path, config: &Config) -> Result<String> {
+    Ok(diff(&document(home)?.to_string(), &render(home, config)?))
+}
+
+fn document(home: &Path) -> Result<DocumentMut> {
+    let path = home.join(workspace::CONFIG);
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    std::fs::read_to_string(&path)
+        .context(Kind::Filesystem, "Can't read config file")?
+        .parse::<DocumentMut>()
+        .context(Kind::Config, "Can't parse config file")
+}
+
+fn render(home: &Path, config: &Config) -> Result<String> {
+    let mut document = document(home)?;
+    let updated = toml::to_string(config)
+        .context(Kind::Config, "Can't serialize config")?
+        .parse::<DocumentMut>()
+        .context(Kind::Config, "Can't serialize config")?;
+
+    reconcile(document.as_table_mut(), updated.as_table());
+    Ok(document.to_string())
+}
+
+/// Makes `target` hold what `source` does, editing only the keys whose values differ. Returns
+/// whether keys were added or removed.
+fn reconcile(target: &mut dyn TableLike, source: &dyn TableLike) -> bool {
+    let stale = target
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !source.contains_key(key))
+        .collect::<Vec<_>>();
+    let mut reshaped = !stale.is_empty();
+    for key in stale {
+        target.remove(&key);
+    }
+
+    for (key, item) in source.iter() {
+        if let (Some(table), Some(existing)) = (
+            item.as_table_like(),
+            target.get_mut(key).and_then(Item::as_table_like_mut),
+        ) {
+            // Inline tables keep no spacing of their own for new keys
+            if reconcile(existing, table) {
+                if let Some(inline) = target.get_mut(key).and_then(Item::as_inline_table_mut) {
+                    inline.fmt();
+                }
+            }
+            continue;
+        }
+
+        if let (Some(value), Some(Item::Value(existing))) = (item.as_value(), target.get_mut(key)) {
+            if !same(existing, value) {
+                // Keeps comments trailing the old value
+                let decor = existing.decor().clone();
+                *existing = value.clone();
+                *existing.decor_mut() = decor;
+            }
+            continue;
+        }
+
+        target.insert(key, item.clone());
+        reshaped = true;
+    }
+
+    reshaped
+}
+
+fn same(a: &Value, b: &Value) -> bool {
+    match (a.as_str(), b.as_str()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.clone().decorated("", "").to_string() == b.clone().decorated("", "").to_string(),
+    }
+}
+
+/// Line diff from `old` to `new`, marking removed lines with `-` and added ones with `+`.
+fn diff(old: &str, new: &str) -> String {
+    let (old, new) = (
+        old.lines().collect::<Vec<_>>(),
+        new.lines().collect::<Vec<_>>(),
+    );
+
+    // Longest common subsequence of the lines from each position on
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Upgrades a `0.1.0` config, recovering each url from the clone's `origin` remote.
+fn migrate(home: &Path, text: &str) -> Result<Config> {
+    let legacy: LegacyConfig =
+        toml::from_str(text).context(Kind::Config, "Can't parse config file")?;
+    let lockfile = lock::read(home)?;
+    let mut config = empty();
+
+    for (name, dep) in legacy.dependencies {
+        let (git_dir, mut dependency) = match dep {
+            LegacyDependency::Dep(path) => (path, Dependency::default()),
+            LegacyDependency::DepWithHash(path, branch) => (
+                path,
+                Dependency {
+                    branch: Some(branch),
+                    ..Default::default()
+                },
+            ),
+            LegacyDependency::Tag(path, tag) => (
+                path,
+                Dependency {
+                    tag: Some(tag),
+                    ..Default::default()
+                },
+            ),
+            LegacyDependency::Rev(path, rev) => (
+                path,
+                Dependency {
+                    rev: Some(rev),
+                    ..Default::default()
+                },
+            ),
+        };
+
+        let checkout = PathBuf::from(git_dir.trim_end_matches(['/', '\\']))
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|| home.join(&name));
+        if checkout != home.join(&name) {
+            dependency.path = Some(
+                checkout
+                    .strip_prefix(home)
+                    .unwrap_or(&checkout)
+                    .to_path_buf(),
+            );
+        }
+
+        dependency.url = match Repository::open(&checkout) {
+            Ok(repo) => git::origin_url(&repo),
+            Err(_) => lockfile
+                .dependencies
+                .get(&name)
+                .map(|locked| locked.url.clone())
+                .context(Kind::Config, "Can't find the url of the clone to migrate"),
+        }
+        .map_err(|e| e.dependency(&name))?;
+
+        config.dependencies.insert(name, dependency);
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` edited to hold what `updated` does.
+    fn edit(text: &str, updated: &str) -> String {
+        let mut document = text.parse::<DocumentMut>().unwrap();
+        let updated = updated.parse::<DocumentMut>().unwrap();
+        reconcile(document.as_table_mut(), updated.as_table());
+        document.to_string()
+    }
+
+    const CONFIG: &str = r#"# Vendored for the build
+version = "0.2.0"
+
+[dependencies]
+# Our fork
+alpha = { url = "https://example.com/a/alpha", branch = "main" } # until 1.0 lands
+beta   = { url = "https://example.com/a/beta" }
+"#;
+
+    #[test]
+    fn reconcile_unchanged() {
+        assert_eq!(edit(CONFIG, CONFIG), CONFIG);
+    }
+
+    #[test]
+    fn reconcile_edit_keeps_comments() {
+        let updated = CONFIG.replace(r#"branch = "main""#, r#"branch = "dev""#);
+        assert_eq!(edit(CONFIG, &updated), updated);
+    }
+
+    #[test]
+    fn reconcile_removal_keeps_comments() {
+        let updated = r#"
+version = "0.2.0"
+
+[dependencies]
+alpha = { url = "https://example.com/a/alpha", branch = "main" }
+"#;
+        assert_eq!(
+            edit(CONFIG, updated),
+            r#"# Vendored for the build
+version = "0.2.0"
+
+[dependencies]
+# Our fork
+alpha = { url = "https://example.com/a/alpha", branch = "main" } # until 1.0 lands
+"#
+        );
+    }
+
+    #[test]
+    fn reconcile_formats_reshaped_inline_tables() {
+        let updated = r#"
+version = "0.2.0"
+
+[dependencies]
+alpha = { url = "https://example.com/a/alpha" }
+beta = { url = "https://example.com/a/beta", tag = "v1" }
+"#;
+        assert_eq!(
+            edit(CONFIG, updated),
+            r#"# Vendored for the build
+version = "0.2.0"
+
+[dependencies]
+# Our fork
+alpha = { url = "https://example.com/a/alpha" } # until 1.0 lands
+beta   = { url = "https://example.com/a/beta", tag = "v1" }
+"#
+        );
+    }
+
+    #[test]
+    fn reconcile_adds_new_keys() {
+        let updated = format!("{CONFIG}gamma = {{ url = \"https://example.com/a/gamma\" }}\n");
+        assert_eq!(edit(CONFIG, &updated), updated);
+    }
+
+    #[test]
+    fn reconcile_serialized_config() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        config.dependencies.get_mut("alpha").unwrap().branch = Some("dev".to_string());
+        config.dependencies.remove("beta");
+        let updated = toml::to_string(&config).unwrap();
+
+        assert_eq!(
+            edit(CONFIG, &updated),
+            r#"# Vendored for the build
+version = "0.2.0"
+
+[dependencies]
+# Our fork
+alpha = { url = "https://example.com/a/alpha", branch = "dev" } # until 1.0 lands
+"#
+        );
+    }
+
+    #[test]
+    fn diff_marks_changed_lines() {
+        assert_eq!(diff("a\nb\nc\n", "a\nx\nc\nd\n"), "  a\n- b\n+ x\n  c\n+ d");
+        assert_eq!(diff("a\n", "a\n"), "  a");
+        assert_eq!(diff("", "a\n"), "+ a");
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Broad category of a failure, each with a stable exit code for scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Missing, unreadable or invalid config, lockfile or manifest (exit code 3)
+    Config,
+    /// Remote couldn't be reached or refused the connection (exit code 4)
+    Network,
+    /// Any other repository operation failed (exit code 5)
+    Git,
+    /// Reading or writing files failed (exit code 6)
+    Filesystem,
+    /// Local state prevents the operation, like uncommitted or diverged work (exit code 7)
+    Conflict,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Config => "config",
+            Kind::Network => "network",
+            Kind::Git => "git",
+            Kind::Filesystem => "filesystem",
+            Kind::Conflict => "conflict",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Kind::Config => 3,
+            Kind::Network => 4,
+            Kind::Git => 5,
+            Kind::Filesystem => 6,
+            Kind::Conflict => 7,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: Kind,
+    pub message: String,
+    pub dependency: Option<String>,
+    pub hint: Option<String>,
+    pub source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl Error {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            dependency: None,
+            hint: None,
+            source: None,
+        }
+    }
+
+    /// Attaches the underlying error, reclassifying git transport failures as network errors.
+    pub fn source(mut self, source: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        let source = source.into();
+
+        if let Some(error) = source.downcast_ref::<git2::Error>() {
+            if matches!(
+                error.class(),
+                git2::ErrorClass::Net
+                    | git2::ErrorClass::Http
+                    | git2::ErrorClass::Ssh
+                    | git2::ErrorClass::Ssl
+            ) {
+                self.kind = Kind::Network;
+            }
+            if error.code() == git2::ErrorCode::Auth {
+                self.hint.get_or_insert_with(|| {
+                    "Set `ssh-key` or `token-env` under `[auth.\"<host>\"]` in config.toml"
+                        .to_string()
+                });
+            }
+        }
+
+        self.source = Some(source);
+        self
+    }
+
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Names the dependency the error happened in, keeping the innermost name.
+    pub fn dependency(mut self, name: &str) -> Self {
+        self.dependency.get_or_insert_with(|| name.to_string());
+        self
+    }
+
+    pub fn summary(&self) -> String {
+        match &self.dependency {
+            Some(name) => format!("[{name}] {}", self.message),
+            None => self.message.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())?;
+        if let Some(source) = &self.source {
+            write!(f, ": {source}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
+
+pub trait Context<T> {
+    fn context(self, kind: Kind, message: impl Into<String>) -> Result<T>;
+}
+
+impl<T, E: std::error::Error + Send + Sync + 'static> Context<T> for std::result::Result<T, E> {
+    fn context(self, kind: Kind, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| Error::new(kind, message).source(e))
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, kind: Kind, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| Error::new(kind, message))
+    }
+}
+
+pub trait ForDependency<T> {
+    fn dependency(self, name: &str) -> Result<T>;
+}
+
+impl<T> ForDependency<T> for Result<T> {
+    fn dependency(self, name: &str) -> Result<T> {
+        self.map_err(|e| e.dependency(name))
+    }
+}
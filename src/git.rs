@@ -0,0 +1,822 @@
+use std::{collections::BTreeMap, path::Path};
+
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    AutotagOption, Branch, BranchType, Commit, Direction, ErrorCode, FetchOptions, Index, Oid,
+    Rebase, RebaseOptions, Remote, RemoteCallbacks, Repository, RepositoryState, ResetType,
+    Signature, Sort, StatusOptions,
+};
+
+use crate::{
+    auth,
+    error::{Context, Error, Kind, Result},
+    progress::Line,
+    types::{Auth, Dependency, Reference, Strategy},
+};
+
+/// Where `--dry-run` fetches to, so none of the clone's own refs move.
+const SCRATCH: &str = "refs/vendman/dry-run";
+
+/// Progress reporting plus credentials for whichever host `url` is on.
+fn callbacks<'a>(
+    url: &str,
+    hosts: &'a BTreeMap<String, Auth>,
+    line: &'a Line,
+) -> RemoteCallbacks<'a> {
+    let mut callbacks = line.callbacks();
+    auth::credentials(&mut callbacks, url, hosts);
+    callbacks
+}
+
+pub fn fetch(
+    repo: &Repository,
+    hosts: &BTreeMap<String, Auth>,
+    line: &Line,
+) -> Result<(), git2::Error> {
+    let mut remote = repo.find_remote("origin")?;
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(remote.url().unwrap_or_default(), hosts, line));
+
+    remote.fetch(&[] as &[&str], Some(&mut options), None)
+}
+
+/// Clones `dep` into `path` and checks out what it's pinned to, returning the resolved reference.
+pub fn clone(
+    dep: &Dependency,
+    path: &Path,
+    hosts: &BTreeMap<String, Auth>,
+    line: &Line,
+) -> Result<(Repository, String)> {
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(&dep.url, hosts, line));
+
+    let mut builder = RepoBuilder::new();
+    builder
+        .fetch_options(options)
+        .with_checkout(line.checkout());
+    if let Reference::Branch(branch) = dep.reference() {
+        builder.branch(branch);
+    }
+
+    let repo = builder
+        .clone(&dep.url, path)
+        .context(Kind::Network, "Can't clone repository")?;
+
+    let reference = match dep.reference() {
+        Reference::Branch(branch) => branch.to_string(),
+        Reference::Tag(tag) => {
+            checkout(&repo, None, &resolve(&repo, &format!("refs/tags/{tag}"))?)?;
+            tag.to_string()
+        }
+        Reference::Rev(rev) => {
+            let commit = resolve(&repo, rev)?;
+            checkout(&repo, None, &commit)?;
+            commit.id().to_string()
+        }
+        Reference::Default => {
+            tracked_branch(&repo).context(Kind::Git, "Cloned HEAD is detached")?
+        }
+    };
+
+    Ok((repo, reference))
+}
+
+pub fn fetch_tag(
+    repo: &Repository,
+    tag: &str,
+    hosts: &BTreeMap<String, Auth>,
+    line: &Line,
+) -> Result<(), git2::Error> {
+    let mut remote = repo.find_remote("origin")?;
+
+    // Auto-followed tags are never overwritten, so moved tags need autotag disabled
+    let mut options = FetchOptions::new();
+    options
+        .download_tags(AutotagOption::None)
+        .remote_callbacks(callbacks(remote.url().unwrap_or_default(), hosts, line));
+
+    remote.fetch(
+        &[format!("+refs/tags/{tag}:refs/tags/{tag}")],
+        Some(&mut options),
+        None,
+    )
+}
+
+/// Fetches `reference` (`refs/heads/..` or `refs/tags/..`) from origin into a scratch ref and
+/// returns the commit it points to, leaving every ref of the clone where it was.
+pub fn peek(
+    repo: &Repository,
+    reference: &str,
+    hosts: &BTreeMap<String, Auth>,
+    line: &Line,
+) -> Result<Oid> {
+    let url = origin_url(repo)?;
+    // Anonymous, so origin's configured refspec doesn't move remote-tracking branches too
+    let mut remote = repo
+        .remote_anonymous(&url)
+        .context(Kind::Git, "Can't create remote")?;
+    let scratch = format!("{SCRATCH}/{}", reference.trim_start_matches("refs/"));
+
+    let mut options = FetchOptions::new();
+    options
+        .download_tags(AutotagOption::None)
+        .update_fetchhead(false)
+        .remote_callbacks(callbacks(&url, hosts, line));
+    remote
+        .fetch(
+            &[format!("+{reference}:{scratch}")],
+            Some(&mut options),
+            None,
+        )
+        .context(Kind::Network, format!("Can't fetch {reference}"))?;
+
+    let oid = resolve(repo, &scratch)?.id();
+    repo.find_reference(&scratch)
+        .and_then(|mut scratch| scratch.delete())
+        .context(Kind::Git, "Can't remove scratch ref")?;
+
+    Ok(oid)
+}
+
+/// The reference `clone` would check out for `dep` and its commit, asked of the remote without
+/// cloning. Revisions only resolve here when they are the tip of some remote ref.
+pub fn ls_remote(
+    dep: &Dependency,
+    hosts: &BTreeMap<String, Auth>,
+    line: &Line,
+) -> Result<(String, Option<Oid>)> {
+    let mut remote = Remote::create_detached(dep.url.as_str())
+        .context(Kind::Config, format!("Invalid url {}", dep.url))?;
+    let connection = remote
+        .connect_auth(
+            Direction::Fetch,
+            Some(callbacks(&dep.url, hosts, line)),
+            None,
+        )
+        .context(Kind::Network, "Can't connect to remote")?;
+    let heads = connection
+        .list()
+        .context(Kind::Network, "Can't list remote refs")?;
+    let find = |name: &str| {
+        heads
+            .iter()
+            .find(|head| head.name() == name)
+            .map(|head| head.oid())
+    };
+
+    match dep.reference() {
+        Reference::Branch(branch) => {
+            let oid = find(&format!("refs/heads/{branch}"))
+                .context(Kind::Config, format!("No branch {branch} on the remote"))?;
+            Ok((branch.to_string(), Some(oid)))
+        }
+        Reference::Tag(tag) => {
+            // Annotated tags list the commit they point to as `<tag>^{}`
+            let oid = find(&format!("refs/tags/{tag}^{{}}"))
+                .or_else(|| find(&format!("refs/tags/{tag}")))
+                .context(Kind::Config, format!("No tag {tag} on the remote"))?;
+            Ok((tag.to_string(), Some(oid)))
+        }
+        Reference::Rev(rev) => Ok((
+            rev.to_string(),
+            heads
+                .iter()
+                .map(|head| head.oid())
+                .find(|oid| oid.to_string().starts_with(rev)),
+        )),
+        Reference::Default => {
+            let head = connection
+                .default_branch()
+                .context(Kind::Network, "Can't find the remote's default branch")?;
+            let head = head.as_str().unwrap_or_default();
+            Ok((
+                head.trim_start_matches("refs/heads/").to_string(),
+                find(head),
+            ))
+        }
+    }
+}
+
+pub fn head_id(repo: &Repository) -> Result<Oid> {
+    Ok(repo
+        .head()
+        .and_then(|h| h.peel_to_commit())
+        .context(Kind::Git, "Can't get head")?
+        .id())
+}
+
+pub fn resolve<'r>(repo: &'r Repository, spec: &str) -> Result<Commit<'r>> {
+    repo.revparse_single(spec)
+        .and_then(|object| object.peel_to_commit())
+        .context(Kind::Git, format!("Can't resolve {spec}"))
+}
+
+/// Canonical form of a remote url for comparisons, without trailing slashes or `.git`.
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url).to_string()
+}
+
+/// Owner and repository name from a remote url, e.g. `github.com/a/utils.git` -> `(a, utils)`.
+pub fn url_name(url: &str) -> (Option<String>, String) {
+    let url = normalize_url(url);
+
+    // The host is never the owner, `https://host/repo` and `git@host:repo` have none
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/').map_or("", |(_, path)| path),
+        None => match url.split_once(':') {
+            Some((host, path)) if !host.contains('/') => path,
+            _ => &url,
+        },
+    };
+    let mut segments = path.rsplit('/').filter(|s| !s.is_empty());
+    match segments.next() {
+        Some(name) => (segments.next().map(|s| s.to_string()), name.to_string()),
+        None => (
+            None,
+            url.rsplit(['/', ':', '@'])
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+    }
+}
+
+pub fn tracked_branch(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    match head.is_branch() {
+        true => head.shorthand().map(|s| s.to_string()),
+        false => None,
+    }
+}
+
+pub fn advance(repo: &Repository, branch: &str, strategy: Strategy) -> Result<(Oid, Oid)> {
+    idle(repo)?;
+
+    let old = head_id(repo)?;
+
+    let upstream = repo
+        .find_branch(&format!("origin/{branch}"), BranchType::Remote)
+        .context(
+            Kind::Git,
+            format!("Can't find remote branch origin/{branch}"),
+        )?
+        .get()
+        .peel_to_commit()
+        .context(Kind::Git, "Can't peel to commit")?;
+
+    let local = match repo.find_branch(branch, BranchType::Local) {
+        Ok(local) => local,
+        Err(_) => repo
+            .branch(branch, &upstream, false)
+            .context(Kind::Git, format!("Can't create branch {branch}"))?,
+    };
+
+    let refname = local
+        .get()
+        .name()
+        .context(Kind::Git, "Branch name isn't valid utf-8")?
+        .to_string();
+    let tip = local
+        .get()
+        .peel_to_commit()
+        .context(Kind::Git, "Can't peel to commit")?;
+    let (fast_forward, ahead) = relation(repo, tip.id(), upstream.id())?;
+
+    match strategy {
+        Strategy::Reset => {
+            repo.set_head(&refname)
+                .context(Kind::Git, "Can't set head")?;
+            repo.reset(upstream.as_object(), ResetType::Hard, None)
+                .context(Kind::Git, format!("Can't reset to origin/{branch}"))?;
+        }
+        _ if fast_forward => checkout(repo, Some(branch), &upstream)?,
+        Strategy::FfOnly => return Err(diverged(branch)),
+        // Upstream has nothing the local commits don't already build on
+        Strategy::Rebase | Strategy::Merge if ahead => checkout(repo, Some(branch), &tip)?,
+        Strategy::Rebase => {
+            checkout(repo, Some(branch), &tip)?;
+            rebase(repo, &local, &upstream)?;
+        }
+        Strategy::Merge => {
+            checkout(repo, Some(branch), &tip)?;
+            merge(repo, &tip, &upstream, branch)?;
+        }
+    }
+
+    Ok((old, head_id(repo)?))
+}
+
+/// Where `advance` would leave `branch`, at `tip`, once upstream is at `incoming`, without
+/// touching the clone.
+///
+/// Rebases and merges are tried in memory to catch conflicts. The commit returned for them is
+/// upstream's, as the one they make only exists once made.
+pub fn preview(
+    repo: &Repository,
+    branch: &str,
+    tip: Oid,
+    incoming: Oid,
+    strategy: Strategy,
+) -> Result<Oid> {
+    idle(repo)?;
+
+    let (fast_forward, ahead) = relation(repo, tip, incoming)?;
+    let (files, action) = match strategy {
+        Strategy::Reset => return Ok(incoming),
+        _ if fast_forward => return Ok(incoming),
+        Strategy::FfOnly => return Err(diverged(branch)),
+        Strategy::Rebase | Strategy::Merge if ahead => return Ok(tip),
+        Strategy::Rebase => {
+            let local = repo
+                .find_annotated_commit(tip)
+                .context(Kind::Git, "Can't read local branch")?;
+            let onto = repo
+                .find_annotated_commit(incoming)
+                .context(Kind::Git, "Can't read upstream commit")?;
+            let mut rebase = repo
+                .rebase(
+                    Some(&local),
+                    Some(&onto),
+                    None,
+                    Some(RebaseOptions::new().inmemory(true)),
+                )
+                .context(Kind::Git, "Can't start rebase")?;
+
+            let committer = signature(repo)?;
+            let mut files = Vec::new();
+            while let Some(operation) = rebase.next() {
+                operation.context(Kind::Git, "Can't apply commit")?;
+                files = conflicted(
+                    &rebase
+                        .inmemory_index()
+                        .context(Kind::Git, "Can't read index")?,
+                )?;
+                if !files.is_empty() {
+                    break;
+                }
+                match rebase.commit(None, &committer, None) {
+                    Err(e) if e.code() == ErrorCode::Applied => {}
+                    result => {
+                        result.context(Kind::Git, "Can't commit rebased change")?;
+                    }
+                }
+            }
+            let _ = rebase.abort();
+
+            (files, "Rebase")
+        }
+        Strategy::Merge => {
+            let ours = repo
+                .find_commit(tip)
+                .context(Kind::Git, "Can't find commit")?;
+            let theirs = repo
+                .find_commit(incoming)
+                .context(Kind::Git, "Can't find commit")?;
+            let index = repo
+                .merge_commits(&ours, &theirs, None)
+                .context(Kind::Git, format!("Can't merge origin/{branch}"))?;
+
+            (conflicted(&index)?, "Merge")
+        }
+    };
+
+    match files.is_empty() {
+        true => Ok(incoming),
+        false => Err(Error::new(
+            Kind::Conflict,
+            format!("{action} would stop on conflicts in {}", files.join(", ")),
+        )),
+    }
+}
+
+/// Whether `incoming` fast-forwards `tip`, and whether `tip` already contains it.
+fn relation(repo: &Repository, tip: Oid, incoming: Oid) -> Result<(bool, bool)> {
+    let fast_forward = tip == incoming
+        || repo
+            .graph_descendant_of(incoming, tip)
+            .context(Kind::Git, "Can't compare commits")?;
+    let ahead = !fast_forward
+        && repo
+            .graph_descendant_of(tip, incoming)
+            .context(Kind::Git, "Can't compare commits")?;
+
+    Ok((fast_forward, ahead))
+}
+
+fn diverged(branch: &str) -> Error {
+    Error::new(
+        Kind::Conflict,
+        format!("Local {branch} has diverged from origin/{branch}"),
+    )
+    .hint("Use `--strategy rebase` or `merge` to keep local commits, `reset` to drop them")
+}
+
+/// Fails if a rebase, merge or the like is still underway in `repo`.
+pub fn idle(repo: &Repository) -> Result<()> {
+    let operation = match repo.state() {
+        RepositoryState::Clean => return Ok(()),
+        RepositoryState::Merge => "merge",
+        RepositoryState::Revert | RepositoryState::RevertSequence => "revert",
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "cherry-pick",
+        RepositoryState::Bisect => "bisect",
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => "`git am`",
+        _ => "rebase",
+    };
+
+    // `resume` only knows how to finish the rebases and merges `update` starts
+    let hint = match repo.state() {
+        RepositoryState::Merge | RepositoryState::RebaseMerge => {
+            "Run `vendman update` to finish it, or abort it with git in the clone".to_string()
+        }
+        _ => format!(
+            "Finish or abort the {operation} with git in {}",
+            repo.workdir().unwrap_or(repo.path()).display()
+        ),
+    };
+
+    Err(Error::new(
+        Kind::Conflict,
+        format!("A {operation} is still in progress"),
+    )
+    .hint(hint))
+}
+
+/// Abandons a rebase or merge left in progress, as `git rebase --abort` or `git merge --abort` would.
+pub fn abort(repo: &Repository) -> Result<()> {
+    match repo.state() {
+        RepositoryState::Clean => Ok(()),
+        RepositoryState::RebaseMerge
+        | RepositoryState::Rebase
+        | RepositoryState::RebaseInteractive => repo
+            .open_rebase(None)
+            .and_then(|mut rebase| rebase.abort())
+            .context(Kind::Git, "Can't abort rebase"),
+        _ => {
+            let head = resolve(repo, "HEAD")?;
+            repo.reset(head.as_object(), ResetType::Hard, None)
+                .and_then(|_| repo.cleanup_state())
+                .context(Kind::Git, "Can't abort merge")
+        }
+    }
+}
+
+/// Finishes a rebase or merge `advance` stopped on once its conflicts are resolved, returning
+/// whether there was one.
+pub fn resume(repo: &Repository) -> Result<bool> {
+    match repo.state() {
+        RepositoryState::RebaseMerge => {
+            let rebase = repo
+                .open_rebase(None)
+                .context(Kind::Git, "Can't open rebase in progress")?;
+            replay(repo, rebase, true)?;
+            Ok(true)
+        }
+        RepositoryState::Merge => {
+            let files = conflicts(repo)?;
+            if !files.is_empty() {
+                return Err(stopped(repo, "Merge", &files, "git merge --abort"));
+            }
+
+            let mut parents = vec![resolve(repo, "HEAD")?];
+            let heads = std::fs::read_to_string(repo.path().join("MERGE_HEAD"))
+                .context(Kind::Filesystem, "Can't read MERGE_HEAD")?;
+            for head in heads.lines() {
+                parents.push(resolve(repo, head.trim())?);
+            }
+
+            let message = repo
+                .message()
+                .unwrap_or_else(|_| "Merge upstream".to_string());
+            commit_merge(repo, &message, &parents.iter().collect::<Vec<_>>())?;
+            Ok(true)
+        }
+        _ => idle(repo).map(|_| false),
+    }
+}
+
+/// Identity for commits vendman makes on the user's behalf.
+fn signature(repo: &Repository) -> Result<Signature<'static>> {
+    repo.signature()
+        .or_else(|_| Signature::now("vendman", "update@vendman"))
+        .context(Kind::Git, "Can't create signature")
+}
+
+/// Paths left conflicted in the index by a rebase or merge.
+fn conflicts(repo: &Repository) -> Result<Vec<String>> {
+    conflicted(&repo.index().context(Kind::Git, "Can't read index")?)
+}
+
+fn conflicted(index: &Index) -> Result<Vec<String>> {
+    if !index.has_conflicts() {
+        return Ok(Vec::new());
+    }
+
+    let files = index
+        .conflicts()
+        .context(Kind::Git, "Can't read conflicts")?
+        .filter_map(|conflict| conflict.ok())
+        .filter_map(|conflict| conflict.our.or(conflict.their).or(conflict.ancestor))
+        .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+        .collect();
+
+    Ok(files)
+}
+
+fn stopped(repo: &Repository, what: &str, files: &[String], abort: &str) -> Error {
+    let path = repo.workdir().unwrap_or(repo.path()).display();
+
+    Error::new(
+        Kind::Conflict,
+        format!("{what} stopped on conflicts in {}", files.join(", ")),
+    )
+    .hint(format!(
+        "Resolve them in {path}, `git add` them and run `vendman update` again (or `{abort}`)"
+    ))
+}
+
+/// Replays the local commits of `branch` onto `upstream`, leaving the rebase on disk if it conflicts.
+fn rebase(repo: &Repository, local: &Branch, upstream: &Commit) -> Result<()> {
+    let local = repo
+        .reference_to_annotated_commit(local.get())
+        .context(Kind::Git, "Can't read local branch")?;
+    let onto = repo
+        .find_annotated_commit(upstream.id())
+        .context(Kind::Git, "Can't read upstream commit")?;
+
+    let rebase = repo
+        .rebase(Some(&local), Some(&onto), None, None)
+        .context(Kind::Git, "Can't start rebase")?;
+
+    replay(repo, rebase, false)
+}
+
+/// Commits each rebase operation, starting with the one stopped on when `resuming`.
+fn replay(repo: &Repository, mut rebase: Rebase, resuming: bool) -> Result<()> {
+    let committer = signature(repo)?;
+    let mut current = resuming && rebase.operation_current().is_some();
+
+    loop {
+        if !current {
+            match rebase.next() {
+                Some(operation) => operation.context(Kind::Git, "Can't apply commit")?,
+                None => break,
+            };
+        }
+        current = false;
+
+        let files = conflicts(repo)?;
+        if !files.is_empty() {
+            return Err(stopped(repo, "Rebase", &files, "git rebase --abort"));
+        }
+
+        match rebase.commit(None, &committer, None) {
+            // Upstream already has this change
+            Err(e) if e.code() == ErrorCode::Applied => {}
+            result => {
+                result.context(Kind::Git, "Can't commit rebased change")?;
+            }
+        }
+    }
+
+    rebase
+        .finish(None)
+        .context(Kind::Git, "Can't finish rebase")
+}
+
+/// Merges `upstream` into the checked out `tip`, leaving the merge on disk if it conflicts.
+fn merge(repo: &Repository, tip: &Commit, upstream: &Commit, branch: &str) -> Result<()> {
+    let theirs = repo
+        .find_annotated_commit(upstream.id())
+        .context(Kind::Git, "Can't read upstream commit")?;
+    repo.merge(&[&theirs], None, None)
+        .context(Kind::Git, format!("Can't merge origin/{branch}"))?;
+
+    let files = conflicts(repo)?;
+    if !files.is_empty() {
+        return Err(stopped(repo, "Merge", &files, "git merge --abort"));
+    }
+
+    commit_merge(repo, &format!("Merge origin/{branch}"), &[tip, upstream])
+}
+
+fn commit_merge(repo: &Repository, message: &str, parents: &[&Commit]) -> Result<()> {
+    let tree = repo
+        .index()
+        .and_then(|mut index| index.write_tree())
+        .and_then(|tree| repo.find_tree(tree))
+        .context(Kind::Git, "Can't write merged tree")?;
+    let signature = signature(repo)?;
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        parents,
+    )
+    .context(Kind::Git, "Can't commit merge")?;
+
+    repo.cleanup_state()
+        .context(Kind::Git, "Can't clean up merge state")
+}
+
+/// Safely checks out `commit`, moving `branch` to it or detaching HEAD when there is no branch.
+pub fn checkout(repo: &Repository, branch: Option<&str>, commit: &Commit) -> Result<()> {
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))
+        .context(
+            Kind::Conflict,
+            "Can't checkout tree, working tree has conflicting changes",
+        )?;
+
+    match branch {
+        Some(branch) => {
+            let refname = format!("refs/heads/{branch}");
+            repo.reference(&refname, commit.id(), true, "vendman: checkout")
+                .context(Kind::Git, format!("Can't move branch {branch}"))?;
+            repo.set_head(&refname)
+                .context(Kind::Git, "Can't set head")?;
+        }
+        None => repo
+            .set_head_detached(commit.id())
+            .context(Kind::Git, "Can't detach head")?,
+    }
+
+    Ok(())
+}
+
+pub fn origin_url(repo: &Repository) -> Result<String> {
+    repo.find_remote("origin")
+        .context(Kind::Git, "Can't find remote")?
+        .url()
+        .map(|url| url.to_string())
+        .context(Kind::Git, "Remote url isn't valid utf-8")
+}
+
+/// Whether `oid` is reachable from a remote-tracking branch or tag.
+fn published(repo: &Repository, oid: Oid) -> Result<bool, git2::Error> {
+    for reference in repo.references()? {
+        let reference = reference?;
+        if !(reference.is_remote() || reference.is_tag()) {
+            continue;
+        }
+
+        let Ok(target) = reference.peel_to_commit() else {
+            continue;
+        };
+        if target.id() == oid || repo.graph_descendant_of(target.id(), oid)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Commits reachable from `to` but not `from`, newest first.
+pub fn commits_between(repo: &Repository, from: Oid, to: Oid) -> Result<Vec<Commit<'_>>> {
+    let mut walk = repo.revwalk().context(Kind::Git, "Can't walk history")?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)
+        .and_then(|_| walk.push(to))
+        .and_then(|_| walk.hide(from))
+        .context(Kind::Git, "Can't walk history")?;
+
+    walk.map(|oid| oid.and_then(|oid| repo.find_commit(oid)))
+        .collect::<Result<_, _>>()
+        .context(Kind::Git, "Can't walk history")
+}
+
+/// The tag whose commit is most recent, by commit time.
+pub fn newest_tag(repo: &Repository) -> Result<Option<String>> {
+    let names = repo.tag_names(None).context(Kind::Git, "Can't list tags")?;
+
+    Ok(names
+        .iter()
+        .flatten()
+        .filter_map(|name| {
+            let commit = resolve(repo, &format!("refs/tags/{name}")).ok()?;
+            Some((commit.time().seconds(), name))
+        })
+        .max()
+        .map(|(_, name)| name.to_string()))
+}
+
+/// Counts tracked files with uncommitted changes and untracked files.
+pub fn changes(repo: &Repository) -> Result<(usize, usize)> {
+    let statuses = repo
+        .statuses(Some(StatusOptions::new().include_untracked(true)))
+        .context(Kind::Git, "Can't read status")?;
+
+    Ok(statuses
+        .iter()
+        .fold((0, 0), |(modified, untracked), entry| {
+            match entry.status() == git2::Status::WT_NEW {
+                true => (modified, untracked + 1),
+                false => (modified + 1, untracked),
+            }
+        }))
+}
+
+/// Describes work in `repo` that only exists locally: uncommitted changes and unpushed commits.
+///
+/// `base` stands in for HEAD, so the patch commits vendman keeps on top of it don't count.
+pub fn local_changes(repo: &Repository, base: Oid) -> Result<Vec<String>> {
+    let mut changes = Vec::new();
+    let head = head_id(repo)?;
+
+    let statuses = repo
+        .statuses(Some(StatusOptions::new().include_untracked(true)))
+        .context(Kind::Git, "Can't read status")?;
+    if !statuses.is_empty() {
+        changes.push(format!("{} uncommitted changes", statuses.len()));
+    }
+
+    for branch in repo
+        .branches(Some(BranchType::Local))
+        .context(Kind::Git, "Can't list branches")?
+    {
+        let (branch, _) = branch.context(Kind::Git, "Can't read branch")?;
+        let name = branch.name().ok().flatten().unwrap_or("?").to_string();
+        let tip = match branch
+            .get()
+            .peel_to_commit()
+            .context(Kind::Git, "Can't peel to commit")?
+            .id()
+        {
+            tip if tip == head => base,
+            tip => tip,
+        };
+
+        if let Ok(upstream) = repo.find_branch(&format!("origin/{name}"), BranchType::Remote) {
+            let upstream = upstream
+                .get()
+                .peel_to_commit()
+                .context(Kind::Git, "Can't peel to commit")?
+                .id();
+            let (ahead, _) = repo
+                .graph_ahead_behind(tip, upstream)
+                .context(Kind::Git, "Can't compare commits")?;
+            if ahead > 0 {
+                changes.push(format!("{name} has {ahead} unpushed commits"));
+            }
+        } else if !published(repo, tip).context(Kind::Git, "Can't compare commits")? {
+            changes.push(format!("{name} isn't pushed"));
+        }
+    }
+
+    if repo.head_detached().unwrap_or(false)
+        && !published(repo, base).context(Kind::Git, "Can't compare commits")?
+    {
+        changes.push("detached HEAD isn't pushed".to_string());
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_url_strips_git_and_slashes() {
+        assert_eq!(
+            normalize_url("https://github.com/a/utils.git"),
+            "https://github.com/a/utils"
+        );
+        assert_eq!(
+            normalize_url(" https://github.com/a/utils/ "),
+            "https://github.com/a/utils"
+        );
+        assert_eq!(
+            normalize_url("git@github.com:a/utils.git/"),
+            "git@github.com:a/utils"
+        );
+        assert_eq!(normalize_url("/srv/git/utils.git"), "/srv/git/utils");
+        assert_eq!(
+            normalize_url("https://example.com/a/.github"),
+            "https://example.com/a/.github"
+        );
+    }
+
+    #[test]
+    fn url_name_takes_owner_and_repository() {
+        let owned = |owner: &str, repo: &str| (Some(owner.to_string()), repo.to_string());
+
+        assert_eq!(
+            url_name("https://github.com/a/utils.git"),
+            owned("a", "utils")
+        );
+        assert_eq!(url_name("https://github.com/a/utils/"), owned("a", "utils"));
+        assert_eq!(url_name("git@github.com:a/utils.git"), owned("a", "utils"));
+        assert_eq!(url_name("ssh://git@host:2222/a/utils"), owned("a", "utils"));
+        assert_eq!(url_name("file:///srv/git/utils.git"), owned("git", "utils"));
+        assert_eq!(url_name("/srv/git/utils"), owned("git", "utils"));
+    }
+
+    #[test]
+    fn url_name_without_owner() {
+        assert_eq!(url_name("git@host:utils.git"), (None, "utils".to_string()));
+        assert_eq!(url_name("https://host/utils"), (None, "utils".to_string()));
+        assert_eq!(url_name("utils.git"), (None, "utils".to_string()));
+        assert_eq!(url_name("https://host/"), (None, "host".to_string()));
+    }
+}
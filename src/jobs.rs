@@ -0,0 +1,43 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+/// Runs `task` over `items` on up to `jobs` threads, returning results in the order of `items`.
+pub fn run<T, R, F>(items: Vec<T>, jobs: usize, task: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let count = items.len();
+    let items = items
+        .into_iter()
+        .map(Some)
+        .map(Mutex::new)
+        .collect::<Vec<_>>();
+    let results = (0..count).map(|_| Mutex::new(None)).collect::<Vec<_>>();
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, count.max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= count {
+                    break;
+                }
+
+                let item = items[index].lock().unwrap().take().unwrap();
+                *results[index].lock().unwrap() = Some(task(item));
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap().unwrap())
+        .collect()
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Context, Kind, Result},
+    snapshot::Snapshot,
+};
+
+/// One JSON record per command that changed the vendman home, oldest first.
+const FILE: &str = "journal.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    /// Seconds since the epoch
+    pub time: i64,
+    pub user: String,
+    pub command: String,
+    /// Clones that moved, appeared or went away
+    pub dependencies: BTreeMap<String, Commits>,
+    /// Everything as the command left it, for `checkout-state`
+    pub state: Snapshot,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Commits {
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// How this process was invoked, quoting arguments with spaces in them.
+pub fn command_line() -> String {
+    std::iter::once("vendman".to_string())
+        .chain(std::env::args().skip(1).map(|arg| {
+            match arg.is_empty() || arg.contains(char::is_whitespace) {
+                true => format!("{arg:?}"),
+                false => arg,
+            }
+        }))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Appends a record of `command` taking the home from `before` to `after`.
+pub fn append(home: &Path, command: &str, before: &Snapshot, after: &Snapshot) -> Result<()> {
+    let commit = |snapshot: &Snapshot, name: &str| {
+        snapshot
+            .heads
+            .get(name)
+            .and_then(|head| head.commit.clone())
+    };
+    let dependencies = before
+        .heads
+        .keys()
+        .chain(after.heads.keys())
+        .map(|name| {
+            let commits = Commits {
+                before: commit(before, name),
+                after: commit(after, name),
+            };
+            (name.clone(), commits)
+        })
+        .filter(|(_, commits)| commits.before != commits.after)
+        .collect();
+
+    let record = Record {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default(),
+        user: std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string()),
+        command: command.to_string(),
+        dependencies,
+        state: after.clone(),
+    };
+    let line = serde_json::to_string(&record).context(Kind::Config, "Can't serialize journal")?;
+
+    let mut file = File::options()
+        .create(true)
+        .append(true)
+        .open(home.join(FILE))
+        .context(Kind::Filesystem, "Can't open journal")?;
+    writeln!(file, "{line}").context(Kind::Filesystem, "Can't write to journal")
+}
+
+/// Every record, oldest first. Records are numbered from 1 in this order.
+pub fn read(home: &Path) -> Result<Vec<Record>> {
+    let path = home.join(FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    std::fs::read_to_string(path)
+        .context(Kind::Filesystem, "Can't read journal")?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line).context(
+                Kind::Config,
+                format!("Can't parse journal entry {}", index + 1),
+            )
+        })
+        .collect()
+}
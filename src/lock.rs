@@ -0,0 +1,85 @@
+use std::{collections::BTreeMap, path::Path};
+
+use git2::{Oid, Repository};
+
+use crate::{
+    error::{Context, Error, Kind, Result},
+    git,
+    progress::Line,
+    types::{Auth, Dependency, LockedDependency, Lockfile, Reference},
+    workspace,
+};
+
+pub fn read(home: &Path) -> Result<Lockfile> {
+    let path = home.join(workspace::LOCKFILE);
+    if !path.exists() {
+        return Ok(Lockfile {
+            version: "0.1.0".to_string(),
+            dependencies: Default::default(),
+        });
+    }
+
+    toml::from_str(&std::fs::read_to_string(path).context(Kind::Filesystem, "Can't read lockfile")?)
+        .context(Kind::Config, "Can't parse lockfile")
+}
+
+pub fn write(home: &Path, lockfile: &Lockfile) -> Result<()> {
+    workspace::replace(
+        &home.join(workspace::LOCKFILE),
+        toml::to_string(lockfile).context(Kind::Config, "Can't serialize lockfile")?,
+    )
+    .context(Kind::Filesystem, "Can't write to lockfile")
+}
+
+/// Records the commit and tree currently checked out in `repo`.
+pub fn entry(repo: &Repository, reference: &str) -> Result<LockedDependency> {
+    let commit = repo
+        .head()
+        .and_then(|h| h.peel_to_commit())
+        .context(Kind::Git, "Can't get head")?;
+
+    Ok(LockedDependency {
+        url: git::origin_url(repo)?,
+        reference: reference.to_string(),
+        commit: commit.id().to_string(),
+        tree: commit.tree_id().to_string(),
+    })
+}
+
+/// Checks out the locked commit, fetching it if the clone doesn't have it, and returns the old HEAD.
+pub fn checkout(
+    repo: &Repository,
+    dep: &Dependency,
+    locked: &LockedDependency,
+    hosts: &BTreeMap<String, Auth>,
+    line: &Line,
+) -> Result<Oid> {
+    git::idle(repo)?;
+
+    let oid = Oid::from_str(&locked.commit).context(Kind::Config, "Invalid commit in lockfile")?;
+
+    if repo.find_commit(oid).is_err() {
+        git::fetch(repo, hosts, line).context(Kind::Network, "Can't fetch repo")?;
+    }
+
+    let commit = repo.find_commit(oid).context(
+        Kind::Git,
+        format!("Can't find locked commit {}", locked.commit),
+    )?;
+    if commit.tree_id().to_string() != locked.tree {
+        return Err(Error::new(
+            Kind::Conflict,
+            format!("Tree of {} doesn't match the lockfile", locked.commit),
+        ));
+    }
+
+    let branch = match dep.reference() {
+        Reference::Branch(_) | Reference::Default => Some(locked.reference.as_str()),
+        Reference::Tag(_) | Reference::Rev(_) => None,
+    };
+
+    let old = git::head_id(repo)?;
+    git::checkout(repo, branch, &commit)?;
+
+    Ok(old)
+}
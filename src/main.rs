@@ -1,16 +1,67 @@
-use std::{io::Write, path::Path};
+use std::{
+    collections::BTreeMap,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
 
 use clap::{Parser, Subcommand};
-use git2::Repository;
-use termimad::MadSkin;
-use toml::to_string;
-use types::Config;
+use error::{Context, Error, ForDependency, Kind, Result};
+use git2::{Oid, Repository};
+use output::{Change, Entry, Format, Operation, Report, Status, Upstream, Worktree};
+use progress::Progress;
+use types::{Auth, Dependency, LockedDependency, Lockfile, Reference, Strategy};
 
+pub mod auth;
+pub mod cargo;
+pub mod config;
+pub mod error;
+pub mod git;
+pub mod jobs;
+pub mod journal;
+pub mod lock;
+pub mod output;
+pub mod patches;
+pub mod progress;
+pub mod snapshot;
 pub mod types;
+pub mod workspace;
 
 #[derive(Parser, Debug)]
 #[command(author = "RMHedge", version = "0.1.0")]
 struct Args {
+    #[arg(
+        long,
+        global = true,
+        env = "VENDMAN_HOME",
+        help = "Use this vendman directory instead of discovering one"
+    )]
+    home: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t,
+        help = "Output format"
+    )]
+    format: Format,
+
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "format",
+        help = "Shorthand for `--format porcelain`"
+    )]
+    porcelain: bool,
+
+    #[arg(
+        short = 'q',
+        long,
+        global = true,
+        help = "Don't show clone and fetch progress"
+    )]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -18,173 +69,1411 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Command {
     #[command(name = "init", about = "Initialize .vendman directory")]
-    Init,
+    Init {
+        #[arg(
+            short = 'l',
+            long,
+            help = "Create .vendman in the current directory instead of the home directory"
+        )]
+        local: bool,
+    },
     #[command(name = "vend", about = "Clone a repo and add it to the config")]
     Vend {
         #[arg(short = 'r', help = "GitHub repo to clone")]
         repo: String,
 
-        #[arg(short = 'b', help = "Branch to checkout")]
+        #[arg(short = 'n', long, help = "Name to vend the repo under")]
+        name: Option<String>,
+
+        #[arg(short = 'b', long, group = "pin", help = "Branch to checkout")]
         branch: Option<String>,
+
+        #[arg(short = 't', long, group = "pin", help = "Tag to checkout")]
+        tag: Option<String>,
+
+        #[arg(long, group = "pin", help = "Exact revision to checkout")]
+        rev: Option<String>,
+
+        #[arg(
+            short = 's',
+            long,
+            value_enum,
+            help = "How `update` should move the branch when it has local commits"
+        )]
+        strategy: Option<Strategy>,
+        #[arg(
+            long,
+            help = "Show what would be cloned and added to the config without doing it"
+        )]
+        dry_run: bool,
+    },
+    #[command(name = "rm", about = "Remove a dependency and its clone")]
+    Remove {
+        name: String,
+
+        #[arg(short = 'f', long, help = "Remove even if the clone has local changes")]
+        force: bool,
+
+        #[arg(long, help = "Show what would be removed without doing it")]
+        dry_run: bool,
     },
     #[command(name = "clean", about = "Remove .vendman directory")]
-    Clean,
+    Clean {
+        #[arg(long, help = "Show what would be removed without doing it")]
+        dry_run: bool,
+    },
     #[command(name = "update", about = "Update dependencies")]
-    Update,
+    Update {
+        #[arg(
+            short = 's',
+            long,
+            value_enum,
+            help = "How to move local branches to upstream, overriding each dependency's strategy"
+        )]
+        strategy: Option<Strategy>,
+
+        #[arg(
+            long,
+            help = "Also write the commits each update brought in to this markdown file, unless it is rolled back"
+        )]
+        changelog: Option<PathBuf>,
+
+        #[arg(
+            short = 'j',
+            long,
+            default_value_t = 8,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "How many dependencies to fetch at once"
+        )]
+        jobs: u32,
+
+        #[arg(
+            long,
+            help = "Fetch and show where each dependency would move, without moving it"
+        )]
+        dry_run: bool,
+
+        #[arg(
+            long,
+            help = "Keep what succeeded when some dependencies fail, instead of rolling back"
+        )]
+        no_rollback: bool,
+    },
+    #[command(
+        name = "sync",
+        about = "Checkout the exact commits recorded in the lockfile"
+    )]
+    Sync {
+        #[arg(
+            long,
+            help = "Keep what succeeded when some dependencies fail, instead of rolling back"
+        )]
+        no_rollback: bool,
+    },
+    #[command(
+        name = "restore",
+        about = "Clone any dependencies missing from the .vendman directory"
+    )]
+    Restore {
+        #[arg(
+            short = 'j',
+            long,
+            default_value_t = 8,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "How many dependencies to fetch at once"
+        )]
+        jobs: u32,
+
+        #[arg(
+            long,
+            help = "Keep what succeeded when some dependencies fail, instead of rolling back"
+        )]
+        no_rollback: bool,
+    },
+    #[command(
+        name = "patch",
+        about = "Point cargo at vendored crates with [patch] sections",
+        args_conflicts_with_subcommands = true
+    )]
+    Patch {
+        #[command(subcommand)]
+        action: Option<PatchCommand>,
+
+        #[arg(long, help = "Write to .cargo/config.toml instead of Cargo.toml")]
+        config: bool,
+    },
+    #[command(
+        name = "export",
+        about = "Export vendored crates as a cargo directory source"
+    )]
+    Export {
+        #[arg(short = 'd', long, help = "Directory to export crates into")]
+        directory: PathBuf,
+    },
     #[command(name = "ls", about = "List current versions of dependencies")]
     List,
+    #[command(
+        name = "outdated",
+        about = "Fetch and show how far each dependency lags upstream, without touching checkouts"
+    )]
+    Outdated {
+        #[arg(
+            short = 'j',
+            long,
+            default_value_t = 8,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "How many dependencies to fetch at once"
+        )]
+        jobs: u32,
+    },
+    #[command(
+        name = "status",
+        about = "Show local changes and drift from upstream and the lockfile"
+    )]
+    Status,
+    #[command(
+        name = "undo",
+        about = "Put the config, lockfile and clones back as they were before the last command that changed them, or redo what the last undo took back"
+    )]
+    Undo {
+        #[arg(
+            short = 'f',
+            long,
+            help = "Remove clones made since even if they have local changes"
+        )]
+        force: bool,
+    },
+    #[command(
+        name = "log",
+        about = "Show the journal of commands that changed dependencies"
+    )]
+    Log {
+        #[arg(short = 'd', long, help = "Only show changes to this dependency")]
+        dep: Option<String>,
+    },
+    #[command(
+        name = "checkout-state",
+        about = "Put the config, lockfile and clones back as a journal entry left them"
+    )]
+    CheckoutState {
+        #[arg(help = "Number of the journal entry, as `log` shows it")]
+        entry: usize,
+
+        #[arg(
+            short = 'f',
+            long,
+            help = "Remove clones made since even if they have local changes"
+        )]
+        force: bool,
+    },
+}
+
+impl Command {
+    /// Whether the command changes the vendman home, and so needs it to itself.
+    fn mutates(&self) -> bool {
+        matches!(
+            self,
+            Command::Vend { .. }
+                | Command::Remove { .. }
+                | Command::Clean { .. }
+                | Command::Update { .. }
+                | Command::Sync { .. }
+                | Command::Restore { .. }
+                | Command::Patch {
+                    action: Some(_),
+                    ..
+                }
+                | Command::Undo { .. }
+                | Command::CheckoutState { .. }
+        )
+    }
+
+    /// Whether the command only shows what it would change.
+    fn dry_run(&self) -> bool {
+        matches!(
+            self,
+            Command::Vend { dry_run: true, .. }
+                | Command::Remove { dry_run: true, .. }
+                | Command::Clean { dry_run: true }
+                | Command::Update { dry_run: true, .. }
+        )
+    }
+
+    /// Whether the command is recorded in the journal, as everything that changes clones is.
+    fn journaled(&self) -> bool {
+        self.mutates() && !matches!(self, Command::Clean { .. })
+    }
+
+    /// Whether `undo` can take the command back, so the state before it is kept.
+    fn undoable(&self) -> bool {
+        matches!(
+            self,
+            Command::Vend { dry_run: false, .. }
+                | Command::Remove { dry_run: false, .. }
+                | Command::Update { dry_run: false, .. }
+                | Command::Sync { .. }
+                | Command::Restore { .. }
+                | Command::Undo { .. }
+                | Command::CheckoutState { .. }
+        )
+    }
+
+    /// Whether the command puts everything back when some dependencies fail.
+    fn rolls_back(&self) -> bool {
+        matches!(
+            self,
+            Command::Update {
+                no_rollback: false,
+                ..
+            } | Command::Sync { no_rollback: false }
+                | Command::Restore {
+                    no_rollback: false,
+                    ..
+                }
+        )
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum PatchCommand {
+    #[command(
+        name = "refresh",
+        about = "Save a dependency's local commits as its patch series"
+    )]
+    Refresh { name: String },
 }
 
 fn main() {
     let args = Args::parse();
+    let format = match args.porcelain {
+        true => Format::Porcelain,
+        false => args.format,
+    };
 
-    let mut erroneous = MadSkin::default();
-    erroneous
-        .bold
-        .set_fg(termimad::crossterm::style::Color::Red);
+    let error = |e: &Error| {
+        match format {
+            Format::Human => eprintln!("{}", output::render_error(e, format)),
+            // Nothing left to report to if stdout is gone
+            _ => _ = writeln!(io::stdout().lock(), "{}", output::render_error(e, format)),
+        }
+        std::process::exit(e.kind.exit_code());
+    };
 
-    let error = |text: &str| {
-        eprint!("{}", erroneous.inline(&format!("**Error**: {}", text)));
-        std::process::exit(1);
+    // Progress goes to stderr but would only clutter logs and piped output
+    let progress =
+        Progress::new(!args.quiet && format == Format::Human && std::io::stdout().is_terminal());
+
+    let output = process(args, &progress).map_err(|e| error(&e)).unwrap();
+    // A reader that stops early, like `head`, isn't an error
+    match writeln!(std::io::stdout().lock(), "{}", output.render(format)) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            error(&Error::new(Kind::Filesystem, "Can't write output").source(e))
+        }
+        _ => {}
+    }
+
+    if output.exit_code != 0 {
+        std::process::exit(output.exit_code);
+    }
+}
+
+fn process(args: Args, progress: &Progress) -> Result<Report> {
+    let home = match args.command {
+        Command::Init { local: true } => std::env::current_dir()
+            .context(Kind::Filesystem, "Can't read current directory")?
+            .join(workspace::DIRECTORY),
+        _ => workspace::resolve(args.home)?,
     };
 
-    let output = process(args).map_err(|e| error(&e)).unwrap();
-    println!("{}", output);
+    // Held until the command returns, so concurrent commands can't lose each other's writes
+    let _lock = match args.command.mutates() && home.is_dir() {
+        true => Some(workspace::lock(&home, progress)?),
+        false => None,
+    };
+
+    // Read-only commands and dry runs leave an older config as it is
+    if args.command.mutates() && !args.command.dry_run() {
+        config::upgrade(&home)?;
+    }
+
+    // The state before, for the journal and for `undo` to go back to
+    let before = match args.command.journaled() && home.join(workspace::CONFIG).exists() {
+        true => Some(snapshot::take(&home)?),
+        false => None,
+    };
+    let (undoable, rolls_back) = (args.command.undoable(), args.command.rolls_back());
+    let result = run(args.command, &home, progress);
+
+    let Some(before) = before else {
+        return result;
+    };
+    let after = match snapshot::take(&home) {
+        Ok(after) if after != before => after,
+        // Nothing changed, so the last snapshot is still the one to undo
+        _ => return result,
+    };
+
+    let failed = result.as_ref().map_or(true, |report| report.exit_code != 0);
+    if !(failed && rolls_back) {
+        if undoable {
+            snapshot::save(&home, &before)?;
+        }
+        journal::append(&home, &journal::command_line(), &before, &after)?;
+        return result;
+    }
+
+    let reverted = snapshot::rollback(&home, &before, true, false)?;
+    match result {
+        Ok(mut report) => {
+            for (name, result) in reverted {
+                if let Err(e) = result {
+                    report.fail(Entry::new(name, Status::Failed), &e);
+                }
+            }
+            Ok(report.rolled_back())
+        }
+        Err(e) => {
+            let hint = match &e.hint {
+                Some(hint) => format!("Everything was rolled back. {hint}"),
+                None => {
+                    "Everything was rolled back, `--no-rollback` keeps what succeeded".to_string()
+                }
+            };
+            Err(e.hint(hint))
+        }
+    }
 }
 
-fn process(args: Args) -> Result<String, String> {
-    let home = Path::new(&dirs::home_dir().expect("Can't find home directory")).join(".vendman");
-    let config = home.join("config.toml");
+fn run(command: Command, home: &Path, progress: &Progress) -> Result<Report> {
+    match command {
+        Command::Init { .. } => {
+            if !home.join(workspace::CONFIG).exists() {
+                std::fs::create_dir_all(home)
+                    .context(Kind::Filesystem, "Can't create .vendman directory")?;
+                config::write(home, &config::empty())?;
+            }
 
-    let enforce_config = || -> Result<Config, String> {
-        if !config.exists() {
-            return Err(
-                "No .vendman directory found. Run `vendman init` to initialize it.".to_string(),
-            );
+            Ok(Report::new(format!(
+                ".vendman directory initialized at {}",
+                home.display()
+            )))
         }
+        Command::Vend {
+            repo,
+            name,
+            branch,
+            tag,
+            rev,
+            strategy,
+            dry_run,
+        } => {
+            let mut config_file = config::read(home)?;
+            let mut lockfile = lock::read(home)?;
+            let url = repo.trim_end_matches('/').to_string();
 
-        toml::from_str(
-            &std::fs::read_to_string(&config).map_err(|_| "Can't read config file".to_string())?,
-        )
-        .map_err(|_| "Can't parse config file".to_string())
-    };
-
-    match args.command {
-        Command::Init => {
-            if !home.exists() {
-                std::fs::create_dir(&home).map_err(|_| "Can't create .vendman directory")?;
-                std::fs::File::options()
-                    .create(true)
-                    .write(true)
-                    .open(&config)
-                    .map_err(|_| "Can't create config file")?
-                    .write_all(
-                        to_string(&types::Config {
-                            version: "0.1.0".to_string(),
-                            dependencies: Default::default(),
-                        })
-                        .unwrap()
-                        .as_bytes(),
+            let taken = |name: &str| {
+                config_file.dependencies.contains_key(name) || home.join(name).exists()
+            };
+
+            if let Some((existing, _)) = config_file
+                .dependencies
+                .iter()
+                .find(|(_, dep)| git::normalize_url(&dep.url) == git::normalize_url(&url))
+            {
+                return Err(
+                    Error::new(Kind::Conflict, format!("{url} is already vended"))
+                        .dependency(existing),
+                );
+            }
+
+            let name = match name {
+                Some(name)
+                    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) =>
+                {
+                    return Err(
+                        Error::new(Kind::Config, "Name must be a plain directory name")
+                            .dependency(&name),
+                    )
+                }
+                Some(name) if taken(&name) => {
+                    return Err(Error::new(Kind::Conflict, "Name is already taken")
+                        .dependency(&name)
+                        .hint("Pick another `--name`"))
+                }
+                Some(name) => name,
+                None => match git::url_name(&url) {
+                    (_, name) if !taken(&name) => name,
+                    (Some(owner), name) if !taken(&format!("{owner}-{name}")) => {
+                        format!("{owner}-{name}")
+                    }
+                    (_, name) => {
+                        return Err(Error::new(
+                            Kind::Conflict,
+                            "Name is already taken by another repo",
+                        )
+                        .dependency(&name)
+                        .hint("Pick a name with `--name`"))
+                    }
+                },
+            };
+            let name = name.as_str();
+
+            let mut dep = Dependency {
+                url,
+                branch,
+                tag,
+                rev,
+                strategy,
+                ..Default::default()
+            };
+
+            if dry_run {
+                let (reference, commit) =
+                    git::ls_remote(&dep, &config_file.auth, &progress.line(name))
+                        .dependency(name)?;
+                if let (Some(_), Some(commit)) = (&dep.rev, commit) {
+                    dep.rev = Some(commit.to_string());
+                }
+
+                let mut report = Report::plan("Would clone");
+                report.dependencies.push(Entry {
+                    url: Some(dep.url.clone()),
+                    reference: Some(reference),
+                    commit: commit.map(|commit| commit.to_string()),
+                    path: Some(home.join(name).display().to_string()),
+                    patches: Some(patches::series(home, name)?.len()),
+                    ..Entry::new(name, Status::Cloned)
+                });
+
+                config_file.dependencies.insert(name.to_string(), dep);
+                report.snippet = Some(config::preview(home, &config_file)?);
+                return Ok(report);
+            }
+
+            let path = home.join(name);
+            let cloned = git::clone(&dep, &path, &config_file.auth, &progress.line(name))
+                .and_then(|(repo, reference)| {
+                    let locked = lock::entry(&repo, &reference)?;
+                    let applied = patches::apply(&repo, home, name).map_err(|e| {
+                        // There's no clone left to fix the series in
+                        e.hint(format!(
+                            "Fix or remove the patches in {} and vend again",
+                            patches::directory(home, name).display()
+                        ))
+                    })?;
+                    Ok((reference, locked, applied))
+                })
+                .dependency(name);
+            let (reference, locked, applied) = match cloned {
+                Ok(cloned) => cloned,
+                Err(e) => {
+                    // Leave nothing behind that isn't in the config, so vending again works
+                    let _ = std::fs::remove_dir_all(&path);
+                    return Err(e);
+                }
+            };
+            if dep.rev.is_some() {
+                dep.rev = Some(reference);
+            }
+
+            let mut report = Report::new("Cloned");
+            report.dependencies.push(Entry {
+                patches: Some(applied),
+                ..Entry::locked(name, &locked, Status::Cloned)
+            });
+
+            lockfile.dependencies.insert(name.to_string(), locked);
+
+            config_file.dependencies.insert(name.to_string(), dep);
+            config::write(home, &config_file)?;
+            lock::write(home, &lockfile)?;
+
+            Ok(report)
+        }
+        Command::Remove {
+            name,
+            force,
+            dry_run,
+        } => {
+            let mut config_file = config::read(home)?;
+            let path = config_file
+                .dependencies
+                .get(&name)
+                .context(Kind::Config, "Not a dependency")
+                .dependency(&name)?
+                .checkout_path(home, &name);
+
+            if !force && path.exists() {
+                let repo = Repository::open(&path)
+                    .context(Kind::Git, "Can't open repository")
+                    .dependency(&name)?;
+                let changes = patches::local_changes(&repo).dependency(&name)?;
+
+                if !changes.is_empty() {
+                    return Err(Error::new(
+                        Kind::Conflict,
+                        format!("Clone has local changes ({})", changes.join(", ")),
                     )
-                    .map_err(|_| "Can't write to config file")?;
+                    .dependency(&name)
+                    .hint("Use `--force` to remove anyway"));
+                }
+            }
+
+            if dry_run {
+                let url = config_file.dependencies.remove(&name).map(|dep| dep.url);
+
+                let mut report = Report::plan("Would remove");
+                report.dependencies.push(Entry {
+                    url,
+                    path: path.exists().then(|| path.display().to_string()),
+                    ..Entry::new(&name, Status::Removed)
+                });
+                report.snippet = Some(config::preview(home, &config_file)?);
+                return Ok(report);
+            }
+
+            if path.exists() {
+                std::fs::remove_dir_all(&path)
+                    .context(Kind::Filesystem, format!("Can't remove {}", path.display()))
+                    .dependency(&name)?;
             }
 
-            return Ok(termimad::inline("**.vendman directory initialized**").to_string());
+            config_file.dependencies.remove(&name);
+            config::write(home, &config_file)?;
+
+            let mut lockfile = lock::read(home)?;
+            lockfile.dependencies.remove(&name);
+            lock::write(home, &lockfile)?;
+
+            let mut report = Report::new("Removed");
+            report.dependencies.push(Entry::new(name, Status::Removed));
+            Ok(report)
         }
-        Command::Vend { repo, branch } => {
-            let mut config_file = enforce_config()?;
-            let name = repo.split('/').last().unwrap();
+        Command::Clean { dry_run } => {
+            config::read(home)?;
 
-            let repo = Repository::clone(&repo, home.join(name))
-                .map_err(|e| format!("Can't clone repository: {e:?}"))?;
+            if dry_run {
+                let mut entries = std::fs::read_dir(home)
+                    .context(Kind::Filesystem, "Can't read .vendman directory")?
+                    .filter_map(|entry| entry.ok())
+                    .collect::<Vec<_>>();
+                entries.sort_by_key(|entry| entry.file_name());
 
-            let dep = match branch {
-                Some(branch) => types::Dependency::DepWithHash(
-                    repo.path().to_str().unwrap().to_string(),
-                    branch,
-                ),
-                None => types::Dependency::Dep(repo.path().to_str().unwrap().to_string()),
+                let mut report = Report::plan(format!("Would remove {}", home.display()));
+                for entry in entries {
+                    report.dependencies.push(Entry {
+                        path: Some(entry.path().display().to_string()),
+                        ..Entry::new(entry.file_name().to_string_lossy(), Status::Removed)
+                    });
+                }
+                return Ok(report);
+            }
+
+            std::fs::remove_dir_all(home)
+                .context(Kind::Filesystem, "Can't remove .vendman directory")?;
+            Ok(Report::new(".vendman directory removed"))
+        }
+        Command::Update {
+            strategy,
+            changelog,
+            jobs,
+            dry_run,
+            no_rollback,
+        } => {
+            let config_file = config::read(home)?;
+            let mut lockfile = lock::read(home)?;
+            let mut report = match dry_run {
+                true => Report::plan("Would update dependencies"),
+                false => Report::new("Dependencies updated"),
             };
 
-            config_file.dependencies.insert(name.to_string(), dep);
-            std::fs::write(config, to_string(&config_file).unwrap())
-                .map_err(|_| "Can't write to config file")?;
+            let results = jobs::run(
+                config_file.dependencies.iter().collect(),
+                jobs as usize,
+                |(name, dep)| {
+                    match dry_run {
+                        true => plan(home, name, dep, strategy, &config_file.auth, progress)
+                            .map(|entry| (entry, None)),
+                        false => update(
+                            home,
+                            name,
+                            dep,
+                            lockfile.dependencies.get(name),
+                            strategy,
+                            &config_file.auth,
+                            progress,
+                        ),
+                    }
+                    .dependency(name)
+                },
+            );
+            for ((name, dep), result) in config_file.dependencies.iter().zip(results) {
+                collect(&mut report, &mut lockfile, name, dep, result);
+            }
+
+            if dry_run {
+                return Ok(report.summarize());
+            }
+
+            lock::write(home, &lockfile)?;
 
-            return Ok(termimad::inline(&format!("**Cloned**: *{}*", name)).to_string());
+            // A failure rolls everything back, and the changelog would list undone commits
+            let report = report.summarize();
+            if let Some(changelog) = changelog.filter(|_| report.exit_code == 0 || no_rollback) {
+                std::fs::write(&changelog, report.changelog())
+                    .context(Kind::Filesystem, "Can't write changelog")?;
+            }
+
+            Ok(report)
         }
-        Command::Clean => {
-            enforce_config()?;
-            std::fs::remove_dir_all(&home).map_err(|_| "Can't remove .vendman directory")?;
-            return Ok(termimad::inline("**.vendman directory removed**").to_string());
+        Command::Sync { .. } => {
+            let config_file = config::read(home)?;
+            let lockfile = lock::read(home)?;
+            let mut report = Report::new("Dependencies synced");
+
+            for (name, dep) in &config_file.dependencies {
+                let locked = lockfile
+                    .dependencies
+                    .get(name)
+                    .context(Kind::Config, "Not locked")
+                    .dependency(name)
+                    .map_err(|e| e.hint("Run `vendman update` to lock it"))?;
+
+                let repo = Repository::open(dep.checkout_path(home, name))
+                    .context(Kind::Git, "Can't open repository")
+                    .dependency(name)?;
+                patches::unapply(&repo, Oid::from_str(&locked.commit).ok()).dependency(name)?;
+                let old =
+                    lock::checkout(&repo, dep, locked, &config_file.auth, &progress.line(name))
+                        .dependency(name)?;
+                let applied = patches::apply(&repo, home, name).dependency(name)?;
+
+                let status = match old.to_string() == locked.commit {
+                    true => Status::UpToDate,
+                    false => Status::Synced,
+                };
+                report.dependencies.push(Entry {
+                    previous: Some(old.to_string()),
+                    patches: Some(applied),
+                    ..Entry::locked(name, locked, status)
+                });
+            }
+
+            Ok(report)
         }
-        Command::Update => {
-            let config_file = enforce_config()?;
-            let mut updated = Vec::<String>::new();
+        Command::Restore { jobs, .. } => {
+            let config_file = config::read(home)?;
+            let mut lockfile = lock::read(home)?;
+            let mut report = Report::new("Dependencies restored");
 
-            for (name, dep) in config_file.dependencies {
-                match dep {
-                    types::Dependency::Dep(_) => {
-                        Repository::open(home.join(name.clone()))
-                            .map_err(|_| format!("[{name}] Can't open repository"))?
-                            .find_remote("origin")
-                            .map_err(|_| format!("[{name}] Can't find remote"))?
-                            .fetch(&["origin"], None, None)
-                            .map_err(|_| format!("[{name}] Can't fetch repo"))?;
-
-                        updated.push(format!("{name}"));
+            let results = jobs::run(
+                config_file.dependencies.iter().collect(),
+                jobs as usize,
+                |(name, dep)| {
+                    restore(
+                        home,
+                        name,
+                        dep,
+                        lockfile.dependencies.get(name),
+                        &config_file.auth,
+                        progress,
+                    )
+                    .dependency(name)
+                },
+            );
+            for ((name, dep), result) in config_file.dependencies.iter().zip(results) {
+                collect(&mut report, &mut lockfile, name, dep, result);
+            }
+
+            lock::write(home, &lockfile)?;
+
+            Ok(report.summarize())
+        }
+        Command::Patch {
+            action: Some(PatchCommand::Refresh { name }),
+            ..
+        } => {
+            let config_file = config::read(home)?;
+            let dep = config_file
+                .dependencies
+                .get(&name)
+                .context(Kind::Config, "Not in the config")
+                .dependency(&name)?;
+            let mut lockfile = lock::read(home)?;
+
+            let repo = Repository::open(dep.checkout_path(home, &name))
+                .context(Kind::Git, "Can't open repository")
+                .dependency(&name)?;
+            let (locked, written) = patches::refresh(&repo, home, &name, dep).dependency(&name)?;
+            lockfile.dependencies.insert(name.clone(), locked);
+            lock::write(home, &lockfile)?;
+
+            let mut report = Report::new(format!("Refreshed patches for {name}"));
+            for path in written {
+                report.dependencies.push(Entry {
+                    path: Some(path.display().to_string()),
+                    ..Entry::new(
+                        path.file_name().unwrap_or_default().to_string_lossy(),
+                        Status::Refreshed,
+                    )
+                });
+            }
+
+            Ok(report)
+        }
+        Command::Patch { config, .. } => {
+            let config_file = config::read(home)?;
+            let root = cargo::project_root(
+                &std::env::current_dir()
+                    .context(Kind::Filesystem, "Can't read current directory")?,
+            )
+            .context(
+                Kind::Config,
+                "No Cargo.toml found in the current directory or its parents",
+            )?;
+            let sources = cargo::sources(&root);
+
+            let mut patches = Vec::<(String, String, PathBuf)>::new();
+            for (name, dep) in &config_file.dependencies {
+                for (package, path) in cargo::packages(&dep.checkout_path(home, name)) {
+                    for source in sources.get(&package).into_iter().flatten() {
+                        patches.push((source.clone(), package.clone(), path.clone()));
                     }
-                    types::Dependency::DepWithHash(_, branch) => {
-                        Repository::open(home.join(name.clone()))
-                            .map_err(|_| format!("[{name}] Can't open repository"))?
-                            .find_remote("origin")
-                            .map_err(|_| format!("[{name}] Can't find remote"))?
-                            .fetch(&["origin"], None, None)
-                            .map_err(|_| format!("[{name}] Can't fetch repo"))?;
-
-                        Repository::open(home.join(name.clone()))
-                            .map_err(|_| format!("[{name}] Can't open repository"))?
-                            .checkout_head(None)
-                            .map_err(|_| format!("[{name}] Can't checkout branch"))?;
-
-                        updated.push(format!("{name}/*{branch}*"));
+                }
+            }
+
+            if patches.is_empty() {
+                return Ok(Report::new(
+                    "No vendored crates are in the dependency graph",
+                ));
+            }
+
+            let manifest = match config {
+                true => root.join(".cargo").join("config.toml"),
+                false => root.join("Cargo.toml"),
+            };
+            cargo::write_patches(&manifest, &root, &patches)?;
+
+            let mut report = Report::new(format!("Patched {}", manifest.display()));
+            for (source, package, path) in patches {
+                report.dependencies.push(Entry {
+                    url: Some(source),
+                    path: Some(path.display().to_string()),
+                    ..Entry::new(package, Status::Patched)
+                });
+            }
+
+            Ok(report)
+        }
+        Command::Export { directory } => {
+            let config_file = config::read(home)?;
+            let mut exported = Vec::<String>::new();
+            let mut report = Report::new(format!("Exported to {}", directory.display()));
+
+            std::fs::create_dir_all(&directory).context(
+                Kind::Filesystem,
+                format!("Can't create {}", directory.display()),
+            )?;
+            let checksummed = std::env::current_dir()
+                .ok()
+                .and_then(|dir| cargo::project_root(&dir))
+                .map(|root| cargo::checksummed(&root))
+                .unwrap_or_default();
+
+            for (name, dep) in &config_file.dependencies {
+                let clone = dep.checkout_path(home, name);
+                for (package, path) in cargo::packages(&clone) {
+                    let version = cargo::package_version(&path, &clone);
+                    let hint = version
+                        .as_ref()
+                        .filter(|&version| checksummed.contains(&(package.clone(), version.clone())))
+                        .map(|version| {
+                            format!(
+                                "Cargo.lock holds the crates.io checksum of {package} {version}, which an export can't match; remove that `checksum` line"
+                            )
+                        });
+                    let crate_name = match exported.contains(&package) {
+                        true => format!(
+                            "{package}-{}",
+                            version
+                                .context(Kind::Config, format!("{package} has no version"))
+                                .dependency(name)?
+                        ),
+                        false => package,
+                    };
+
+                    let target = directory.join(&crate_name);
+                    cargo::export(&path, &clone, &target).dependency(name)?;
+
+                    report.dependencies.push(Entry {
+                        path: Some(target.display().to_string()),
+                        hint,
+                        ..Entry::new(&crate_name, Status::Exported)
+                    });
+                    exported.push(crate_name);
+                }
+            }
+
+            report.snippet = Some(format!(
+                "[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n[source.vendored-sources]\ndirectory = \"{}\"",
+                directory.display()
+            ));
+
+            Ok(report)
+        }
+        Command::Outdated { jobs } => {
+            let config_file = config::read(home)?;
+            let mut report = Report::new("Upstream");
+
+            let results = jobs::run(
+                config_file.dependencies.iter().collect(),
+                jobs as usize,
+                |(name, dep)| {
+                    outdated(home, name, dep, &config_file.auth, progress).dependency(name)
+                },
+            );
+            for ((name, dep), result) in config_file.dependencies.iter().zip(results) {
+                match result {
+                    Ok(entry) => report.dependencies.push(entry),
+                    Err(e) => report.fail(
+                        Entry {
+                            url: Some(dep.url.clone()),
+                            ..Entry::new(name, Status::Failed)
+                        },
+                        &e,
+                    ),
+                }
+            }
+
+            Ok(report)
+        }
+        Command::Status => {
+            let config_file = config::read(home)?;
+            let lockfile = lock::read(home)?;
+            let mut report = Report::new("Dependency status");
+
+            for (name, dep) in &config_file.dependencies {
+                match status(home, name, dep, lockfile.dependencies.get(name)) {
+                    Ok(entry) => report.dependencies.push(entry),
+                    Err(e) => report.fail(
+                        Entry {
+                            url: Some(dep.url.clone()),
+                            ..Entry::new(name, Status::Failed)
+                        },
+                        &e,
+                    ),
+                }
+            }
+
+            Ok(report)
+        }
+        Command::Undo { force } => {
+            let snapshot = snapshot::read(home)?;
+            restore_state(home, &snapshot, force, Report::new("Undone"), progress)
+        }
+        Command::Log { dep } => {
+            let mut report = Report::new("Journal");
+
+            let records = journal::read(home)?;
+            for (index, record) in records.iter().enumerate().rev() {
+                let operation = Operation {
+                    id: index + 1,
+                    date: output::timestamp(record.time),
+                    user: record.user.clone(),
+                    command: record.command.clone(),
+                };
+
+                for (name, commits) in &record.dependencies {
+                    if dep.as_ref().is_some_and(|dep| dep != name) {
+                        continue;
                     }
+
+                    let status = match (&commits.before, &commits.after) {
+                        (None, _) => Status::Cloned,
+                        (_, None) => Status::Removed,
+                        _ => Status::Updated,
+                    };
+                    report.dependencies.push(Entry {
+                        commit: commits.after.clone(),
+                        previous: commits.before.clone(),
+                        operation: Some(operation.clone()),
+                        ..Entry::new(name, status)
+                    });
                 }
             }
 
-            return Ok(termimad::inline("**Dependencies updated**").to_string());
+            Ok(report)
+        }
+        Command::CheckoutState { entry, force } => {
+            let records = journal::read(home)?;
+            let record = entry
+                .checked_sub(1)
+                .and_then(|index| records.get(index))
+                .context(Kind::Config, format!("No journal entry #{entry}"))
+                .map_err(|e| e.hint("Run `vendman log` to see the entries"))?;
+
+            restore_state(
+                home,
+                &record.state,
+                force,
+                Report::new(format!("Checked out state #{entry}")),
+                progress,
+            )
         }
         Command::List => {
-            let config_file = enforce_config()?;
-            let mut table = Vec::<(String, String, String)>::new();
-
-            for (name, _) in config_file.dependencies {
-                let repo = Repository::open(home.join(name.clone()))
-                    .map_err(|_| "Can't open repository")?;
-                let head = repo.head().map_err(|_| "Can't get head")?;
-                let commit = head.peel_to_commit().map_err(|_| "Can't peel to commit")?;
-
-                let branch = head.shorthand().unwrap_or("HEAD").to_string();
-                let hash = commit.id().to_string();
-
-                table.push((name, branch, hash));
-            }
-
-            let mut table = table.iter().collect::<Vec<_>>();
-            table.sort_by(|a, b| a.0.cmp(&b.0));
-
-            termimad::print_text(&format!(
-                "\n|**Name**|**Branch**|**Hash**|\n|:-:|:-:|:-:|\n{}\n",
-                table
-                    .iter()
-                    .map(|(name, branch, hash)| format!(
-                        "| **{}** | {} | *{}* |",
-                        name, branch, hash
-                    ))
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            ));
+            let config_file = config::read(home)?;
+            let mut report = Report::new("Dependencies");
+            report.table = true;
+
+            for (name, dep) in config_file.dependencies {
+                let repo = Repository::open(dep.checkout_path(home, &name))
+                    .context(Kind::Git, "Can't open repository")
+                    .dependency(&name)?;
+                let head = repo
+                    .head()
+                    .context(Kind::Git, "Can't get head")
+                    .dependency(&name)?;
+                let commit = head
+                    .peel_to_commit()
+                    .context(Kind::Git, "Can't peel to commit")
+                    .dependency(&name)?;
+
+                report.dependencies.push(Entry {
+                    url: git::origin_url(&repo).ok(),
+                    reference: head.shorthand().map(|s| s.to_string()),
+                    commit: Some(commit.id().to_string()),
+                    ..Entry::new(name, Status::Present)
+                });
+            }
+
+            Ok(report)
+        }
+    }
+}
+
+/// Puts the config, lockfile and clones back as `snapshot` has them, cloning again any that went
+/// away since.
+fn restore_state(
+    home: &Path,
+    snapshot: &snapshot::Snapshot,
+    force: bool,
+    mut report: Report,
+    progress: &Progress,
+) -> Result<Report> {
+    let reverted = snapshot::rollback(home, snapshot, false, force)?;
+
+    let config_file = config::read(home)?;
+    let mut lockfile = lock::read(home)?;
+    for (name, result) in reverted {
+        match result {
+            Ok(snapshot::Reverted::Moved(from, to)) => report.dependencies.push(Entry {
+                commit: Some(to.to_string()),
+                previous: Some(from.to_string()),
+                ..Entry::new(name, Status::RolledBack)
+            }),
+            Ok(snapshot::Reverted::Removed(path)) => report.dependencies.push(Entry {
+                path: Some(path.display().to_string()),
+                ..Entry::new(name, Status::Removed)
+            }),
+            Ok(snapshot::Reverted::Missing) => {
+                if let Some(dep) = config_file.dependencies.get(&name) {
+                    let result = restore(
+                        home,
+                        &name,
+                        dep,
+                        lockfile.dependencies.get(&name),
+                        &config_file.auth,
+                        progress,
+                    )
+                    .dependency(&name);
+                    collect(&mut report, &mut lockfile, &name, dep, result);
+                }
+            }
+            Ok(snapshot::Reverted::Unchanged) => {}
+            Err(e) => report.fail(Entry::new(name, Status::Failed), &e),
+        }
+    }
+
+    lock::write(home, &lockfile)?;
+
+    Ok(report)
+}
+
+/// Records the outcome for one dependency of a parallel command, locking it if it moved.
+fn collect(
+    report: &mut Report,
+    lockfile: &mut Lockfile,
+    name: &str,
+    dep: &Dependency,
+    result: Result<(Entry, Option<LockedDependency>)>,
+) {
+    match result {
+        Ok((entry, locked)) => {
+            report.dependencies.push(entry);
+            if let Some(locked) = locked {
+                lockfile.dependencies.insert(name.to_string(), locked);
+            }
+        }
+        Err(e) => report.fail(
+            Entry {
+                url: Some(dep.url.clone()),
+                ..Entry::new(name, Status::Failed)
+            },
+            &e,
+        ),
+    }
+}
 
-            Ok(String::new())
+fn update(
+    home: &Path,
+    name: &str,
+    dep: &Dependency,
+    locked: Option<&LockedDependency>,
+    strategy: Option<Strategy>,
+    hosts: &BTreeMap<String, Auth>,
+    progress: &Progress,
+) -> Result<(Entry, Option<LockedDependency>)> {
+    let line = progress.line(name);
+    let repo = Repository::open(dep.checkout_path(home, name))
+        .context(Kind::Git, "Can't open repository")?;
+
+    if let Reference::Rev(rev) = dep.reference() {
+        let entry = Entry {
+            url: Some(dep.url.clone()),
+            reference: Some(rev.to_string()),
+            commit: Some(git::resolve(&repo, rev)?.id().to_string()),
+            ..Entry::new(name, Status::Pinned)
+        };
+        return Ok((entry, None));
+    }
+
+    // Where the clone was, before the rebase an earlier update stopped on if there's one
+    let before = match repo.open_rebase(None).ok().and_then(|r| r.orig_head_id()) {
+        Some(head) => head,
+        None => git::head_id(&repo)?,
+    };
+    let resumed = git::resume(&repo)?;
+
+    // The series is re-applied on whatever upstream moves to
+    patches::unapply(&repo, locked.and_then(|l| Oid::from_str(&l.commit).ok()))?;
+
+    let (reference, old, new, upstream) = match dep.reference() {
+        Reference::Tag(tag) => {
+            git::fetch_tag(&repo, tag, hosts, &line)
+                .context(Kind::Network, format!("Can't fetch tag {tag}"))?;
+
+            let old = git::head_id(&repo)?;
+            let commit = git::resolve(&repo, &format!("refs/tags/{tag}"))?;
+            git::checkout(&repo, None, &commit)?;
+
+            (tag.to_string(), old, commit.id(), commit.id())
+        }
+        _ => {
+            git::fetch(&repo, hosts, &line).context(Kind::Network, "Can't fetch repo")?;
+
+            let branch = match dep.reference() {
+                Reference::Branch(branch) => branch.to_string(),
+                _ => git::tracked_branch(&repo)
+                    .context(Kind::Git, "HEAD is detached, can't find branch")?,
+            };
+
+            let (old, new) = git::advance(
+                &repo,
+                &branch,
+                strategy.or(dep.strategy).unwrap_or_default(),
+            )?;
+            let upstream = git::resolve(&repo, &format!("refs/remotes/origin/{branch}"))?.id();
+
+            (branch, old, new, upstream)
+        }
+    };
+
+    let locked = lock::entry(&repo, &reference)?;
+    let applied = match patches::apply(&repo, home, name) {
+        Ok(applied) => applied,
+        Err(e) => {
+            // Stay where the series still applies, in step with the lockfile
+            git::checkout(
+                &repo,
+                git::tracked_branch(&repo).as_deref(),
+                &repo
+                    .find_commit(old)
+                    .context(Kind::Git, "Can't find previous commit")?,
+            )?;
+            patches::apply(&repo, home, name)?;
+            return Err(e.hint(format!(
+                "Rebase the clone onto {reference}, fix the conflicts and run `vendman patch refresh {name}`"
+            )));
+        }
+    };
+    let status = match old == new && !resumed {
+        true => Status::UpToDate,
+        false => Status::Updated,
+    };
+    // Only what upstream brought in, not local commits a rebase or merge carried along
+    let changes = changes(&repo, before, upstream)?;
+    let entry = Entry {
+        previous: Some(
+            match resumed {
+                true => before,
+                false => old,
+            }
+            .to_string(),
+        ),
+        patches: Some(applied),
+        changes,
+        ..Entry::locked(name, &locked, status)
+    };
+
+    Ok((entry, Some(locked)))
+}
+
+/// The commits of `upstream` that `old` doesn't have yet, newest first.
+fn changes(repo: &Repository, old: Oid, upstream: Oid) -> Result<Vec<Change>> {
+    Ok(git::commits_between(repo, old, upstream)?
+        .iter()
+        .map(|commit| Change {
+            commit: commit.id().to_string(),
+            author: commit.author().name().unwrap_or("unknown").to_string(),
+            subject: commit.summary().unwrap_or_default().to_string(),
+        })
+        .collect())
+}
+
+/// What `update` would do to one dependency, fetching only into scratch refs.
+fn plan(
+    home: &Path,
+    name: &str,
+    dep: &Dependency,
+    strategy: Option<Strategy>,
+    hosts: &BTreeMap<String, Auth>,
+    progress: &Progress,
+) -> Result<Entry> {
+    let line = progress.line(name);
+    let repo = Repository::open(dep.checkout_path(home, name))
+        .context(Kind::Git, "Can't open repository")?;
+
+    if let Reference::Rev(rev) = dep.reference() {
+        return Ok(Entry {
+            url: Some(dep.url.clone()),
+            reference: Some(rev.to_string()),
+            commit: Some(git::resolve(&repo, rev)?.id().to_string()),
+            ..Entry::new(name, Status::Pinned)
+        });
+    }
+
+    // Where the branch sits once `update` takes the series off
+    let (_, base) = patches::applied(&repo)?;
+    let old = base.id();
+
+    let (reference, new, upstream) = match dep.reference() {
+        Reference::Tag(tag) => {
+            let commit = git::peek(&repo, &format!("refs/tags/{tag}"), hosts, &line)?;
+            (tag.to_string(), commit, commit)
+        }
+        _ => {
+            let branch = match dep.reference() {
+                Reference::Branch(branch) => branch.to_string(),
+                _ => git::tracked_branch(&repo)
+                    .context(Kind::Git, "HEAD is detached, can't find branch")?,
+            };
+            let incoming = git::peek(&repo, &format!("refs/heads/{branch}"), hosts, &line)?;
+            let new = git::preview(
+                &repo,
+                &branch,
+                old,
+                incoming,
+                strategy.or(dep.strategy).unwrap_or_default(),
+            )?;
+
+            (branch, new, incoming)
+        }
+    };
+
+    let commit = repo
+        .find_commit(new)
+        .context(Kind::Git, "Can't find fetched commit")?;
+    let (applied, _) = patches::stack(&repo, home, name, commit).map_err(|e| {
+        e.hint(format!(
+            "Rebase the clone onto {reference}, fix the conflicts and run `vendman patch refresh {name}`"
+        ))
+    })?;
+
+    let status = match old == new {
+        true => Status::UpToDate,
+        false => Status::Updated,
+    };
+    let changes = changes(&repo, old, upstream)?;
+
+    Ok(Entry {
+        url: Some(dep.url.clone()),
+        reference: Some(reference),
+        commit: Some(new.to_string()),
+        previous: Some(old.to_string()),
+        patches: Some(applied),
+        changes,
+        ..Entry::new(name, status)
+    })
+}
+
+fn restore(
+    home: &Path,
+    name: &str,
+    dep: &Dependency,
+    locked: Option<&LockedDependency>,
+    hosts: &BTreeMap<String, Auth>,
+    progress: &Progress,
+) -> Result<(Entry, Option<LockedDependency>)> {
+    let path = dep.checkout_path(home, name);
+
+    if path.exists() {
+        let repo = Repository::open(&path).context(Kind::Git, "Can't open repository")?;
+        let entry = Entry {
+            url: Some(dep.url.clone()),
+            commit: Some(git::head_id(&repo)?.to_string()),
+            ..Entry::new(name, Status::Present)
+        };
+        return Ok((entry, None));
+    }
+
+    let line = progress.line(name);
+    let restored = git::clone(dep, &path, hosts, &line).and_then(|(repo, reference)| {
+        let locked = match locked {
+            Some(locked) => {
+                lock::checkout(&repo, dep, locked, hosts, &line)?;
+                locked.clone()
+            }
+            None => lock::entry(&repo, &reference)?,
+        };
+        Ok((locked, patches::apply(&repo, home, name)?))
+    });
+
+    match restored {
+        Ok((locked, applied)) => {
+            let entry = Entry {
+                patches: Some(applied),
+                ..Entry::locked(name, &locked, Status::Cloned)
+            };
+            Ok((entry, Some(locked)))
+        }
+        Err(e) => {
+            // Leave nothing half-cloned behind so the next restore retries cleanly
+            let _ = std::fs::remove_dir_all(&path);
+            Err(e)
         }
     }
 }
+
+fn status(
+    home: &Path,
+    name: &str,
+    dep: &Dependency,
+    locked: Option<&LockedDependency>,
+) -> Result<Entry> {
+    let path = dep.checkout_path(home, name);
+    if !path.exists() {
+        return Ok(Entry {
+            url: Some(dep.url.clone()),
+            path: Some(path.display().to_string()),
+            ..Entry::new(name, Status::Missing)
+        });
+    }
+
+    let repo = Repository::open(&path).context(Kind::Git, "Can't open repository")?;
+    let (modified, untracked) = git::changes(&repo)?;
+    let (patches, base) = patches::applied(&repo)?;
+
+    // Read-only, so this compares against whatever was last fetched
+    let upstream = match dep.reference() {
+        Reference::Tag(tag) => git::resolve(&repo, &format!("refs/tags/{tag}")).ok(),
+        Reference::Rev(rev) => git::resolve(&repo, rev).ok(),
+        Reference::Branch(branch) => {
+            git::resolve(&repo, &format!("refs/remotes/origin/{branch}")).ok()
+        }
+        Reference::Default => git::tracked_branch(&repo)
+            .and_then(|branch| git::resolve(&repo, &format!("refs/remotes/origin/{branch}")).ok()),
+    };
+    let (ahead, behind) = match upstream {
+        Some(upstream) => {
+            let (ahead, behind) = repo
+                .graph_ahead_behind(base.id(), upstream.id())
+                .context(Kind::Git, "Can't compare commits")?;
+            (Some(ahead), Some(behind))
+        }
+        None => (None, None),
+    };
+
+    let worktree = Worktree {
+        modified,
+        untracked,
+        ahead,
+        behind,
+        locked: locked.map(|locked| locked.commit == base.id().to_string()),
+        detached: repo.head_detached().unwrap_or(false),
+    };
+    let status = match worktree.is_clean() {
+        true => Status::Clean,
+        false => Status::Dirty,
+    };
+
+    Ok(Entry {
+        url: Some(dep.url.clone()),
+        reference: match dep.reference() {
+            Reference::Default => git::tracked_branch(&repo),
+            Reference::Branch(r) | Reference::Tag(r) | Reference::Rev(r) => Some(r.to_string()),
+        },
+        commit: Some(base.id().to_string()),
+        patches: Some(patches),
+        worktree: Some(worktree),
+        ..Entry::new(name, status)
+    })
+}
+
+fn outdated(
+    home: &Path,
+    name: &str,
+    dep: &Dependency,
+    hosts: &BTreeMap<String, Auth>,
+    progress: &Progress,
+) -> Result<Entry> {
+    let repo = Repository::open(dep.checkout_path(home, name))
+        .context(Kind::Git, "Can't open repository")?;
+    // Only moves remote-tracking refs, never the checkout
+    git::fetch(&repo, hosts, &progress.line(name)).context(Kind::Network, "Can't fetch repo")?;
+
+    let (_, base) = patches::applied(&repo)?;
+    let branch = match dep.reference() {
+        Reference::Branch(branch) => Some(branch.to_string()),
+        Reference::Default => git::tracked_branch(&repo),
+        Reference::Tag(_) | Reference::Rev(_) => None,
+    };
+    let latest = match &branch {
+        Some(branch) => git::resolve(&repo, &format!("refs/remotes/origin/{branch}"))?,
+        None => git::resolve(&repo, "refs/remotes/origin/HEAD")?,
+    };
+    let (_, behind) = repo
+        .graph_ahead_behind(base.id(), latest.id())
+        .context(Kind::Git, "Can't compare commits")?;
+
+    let upstream = Upstream {
+        latest: latest.id().to_string(),
+        behind,
+        tag: git::newest_tag(&repo)?,
+        date: output::timestamp(latest.time().seconds()),
+    };
+    let status = match behind {
+        0 => Status::UpToDate,
+        _ => Status::Outdated,
+    };
+
+    Ok(Entry {
+        url: Some(dep.url.clone()),
+        reference: match dep.reference() {
+            Reference::Default => branch,
+            Reference::Branch(r) | Reference::Tag(r) | Reference::Rev(r) => Some(r.to_string()),
+        },
+        commit: Some(base.id().to_string()),
+        upstream: Some(upstream),
+        ..Entry::new(name, status)
+    })
+}
@@ -0,0 +1,663 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{error::Error, types::LockedDependency};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Human,
+    Json,
+    Porcelain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Present,
+    Cloned,
+    Removed,
+    Updated,
+    UpToDate,
+    Pinned,
+    Synced,
+    Patched,
+    Exported,
+    Refreshed,
+    Clean,
+    Dirty,
+    Missing,
+    Outdated,
+    RolledBack,
+    Failed,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Present => "present",
+            Status::Cloned => "cloned",
+            Status::Removed => "removed",
+            Status::Updated => "updated",
+            Status::UpToDate => "up-to-date",
+            Status::Pinned => "pinned",
+            Status::Synced => "synced",
+            Status::Patched => "patched",
+            Status::Exported => "exported",
+            Status::Refreshed => "refreshed",
+            Status::Clean => "clean",
+            Status::Dirty => "dirty",
+            Status::Missing => "missing",
+            Status::Outdated => "outdated",
+            Status::RolledBack => "rolled-back",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+// JSON and porcelain share `name`, so the two can't drift apart
+impl Serialize for Status {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Present => "present",
+            Status::Cloned => "cloned",
+            Status::Removed => "removed",
+            Status::Updated => "updated",
+            Status::UpToDate => "up to date",
+            Status::Pinned => "pinned",
+            Status::Synced => "synced",
+            Status::Patched => "patched",
+            Status::Exported => "exported",
+            Status::Refreshed => "refreshed",
+            Status::Clean => "clean",
+            Status::Dirty => "dirty",
+            Status::Missing => "missing",
+            Status::Outdated => "outdated",
+            Status::RolledBack => "rolled back",
+            Status::Failed => "failed",
+        })
+    }
+}
+
+/// State of a clone relative to its upstream ref and the lockfile, for `status`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Worktree {
+    /// Tracked files with uncommitted changes
+    pub modified: usize,
+    pub untracked: usize,
+    /// Commits of its own, not counting applied patches, and upstream commits it lacks
+    pub ahead: Option<usize>,
+    pub behind: Option<usize>,
+    /// Whether the upstream commit under any patches is the locked one, if it's locked at all
+    pub locked: Option<bool>,
+    pub detached: bool,
+}
+
+impl Worktree {
+    /// No local changes and nothing that disagrees with the lockfile. Not being locked yet is only
+    /// a note.
+    pub fn is_clean(&self) -> bool {
+        self.modified == 0
+            && self.untracked == 0
+            && self.ahead.unwrap_or(0) == 0
+            && self.locked != Some(false)
+    }
+
+    fn human(&self) -> String {
+        let mut notes = Vec::new();
+        if self.modified > 0 {
+            notes.push(format!("{} modified", self.modified));
+        }
+        if self.untracked > 0 {
+            notes.push(format!("{} untracked", self.untracked));
+        }
+        if let Some(ahead) = self.ahead.filter(|&ahead| ahead > 0) {
+            notes.push(format!("{ahead} ahead"));
+        }
+        match self.behind {
+            Some(behind) if behind > 0 => notes.push(format!("{behind} behind")),
+            None => notes.push("no upstream ref".to_string()),
+            _ => {}
+        }
+        match self.locked {
+            Some(false) => notes.push("differs from lockfile".to_string()),
+            None => notes.push("not locked".to_string()),
+            _ => {}
+        }
+        if self.detached {
+            notes.push("detached".to_string());
+        }
+
+        match notes.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", notes.join(", ")),
+        }
+    }
+}
+
+/// Where upstream has got to, for `outdated`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Upstream {
+    /// Head of the tracked branch, or the remote's default branch for tag and rev pins
+    pub latest: String,
+    pub behind: usize,
+    /// Tag on the most recent commit
+    pub tag: Option<String>,
+    /// Commit date of `latest`
+    pub date: String,
+}
+
+impl Upstream {
+    fn human(&self) -> String {
+        let tag = self
+            .tag
+            .as_ref()
+            .map(|tag| format!(", newest tag *{tag}*"))
+            .unwrap_or_default();
+
+        format!(
+            " ({} behind `{}` from {}{tag})",
+            self.behind,
+            short(&self.latest),
+            self.date
+        )
+    }
+}
+
+/// The journal entry a change was made in, for `log`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Operation {
+    /// Number to pass to `checkout-state`
+    pub id: usize,
+    pub date: String,
+    pub user: String,
+    pub command: String,
+}
+
+impl Operation {
+    fn human(&self) -> String {
+        format!(
+            " (#{} by {} at {}: `{}`)",
+            self.id, self.user, self.date, self.command
+        )
+    }
+}
+
+/// One commit an update brought in.
+#[derive(Serialize, Debug, Clone)]
+pub struct Change {
+    pub commit: String,
+    pub author: String,
+    pub subject: String,
+}
+
+impl Change {
+    fn markdown(&self) -> String {
+        format!(
+            "- `{}` {} ({})",
+            short(&self.commit),
+            self.subject,
+            self.author
+        )
+    }
+}
+
+/// Abbreviates a commit hash to seven characters, leaving shorter ones as they are.
+pub fn short(commit: &str) -> &str {
+    commit.get(..7).unwrap_or(commit)
+}
+
+/// Formats seconds since the epoch as a UTC date and time.
+pub fn timestamp(seconds: i64) -> String {
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // Days to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        time / 3600,
+        time % 3600 / 60
+    )
+}
+
+/// One dependency (or crate, for `patch` and `export`) touched by a command.
+#[derive(Serialize, Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub url: Option<String>,
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    pub commit: Option<String>,
+    pub previous: Option<String>,
+    pub path: Option<String>,
+    pub status: Status,
+    /// How many patches from the dependency's series sit on top of `commit`
+    pub patches: Option<usize>,
+    pub worktree: Option<Worktree>,
+    pub upstream: Option<Upstream>,
+    pub operation: Option<Operation>,
+    /// Commits between `previous` and `commit`, newest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<Change>,
+    pub error: Option<String>,
+    /// What to do about `error`
+    pub hint: Option<String>,
+}
+
+impl Entry {
+    pub fn new(name: impl Into<String>, status: Status) -> Self {
+        Self {
+            name: name.into(),
+            url: None,
+            reference: None,
+            commit: None,
+            previous: None,
+            path: None,
+            status,
+            patches: None,
+            worktree: None,
+            upstream: None,
+            operation: None,
+            changes: Vec::new(),
+            error: None,
+            hint: None,
+        }
+    }
+
+    pub fn locked(name: impl Into<String>, locked: &LockedDependency, status: Status) -> Self {
+        Self {
+            url: Some(locked.url.clone()),
+            reference: Some(locked.reference.clone()),
+            commit: Some(locked.commit.clone()),
+            ..Self::new(name, status)
+        }
+    }
+
+    fn human(&self) -> String {
+        let reference = self
+            .reference
+            .as_ref()
+            .map(|r| format!(" *{r}*"))
+            .unwrap_or_default();
+        let commit = match (&self.previous, &self.commit) {
+            (Some(old), Some(new)) if old != new => format!(" `{}` → `{}`", short(old), short(new)),
+            (_, Some(commit)) | (Some(commit), None) => format!(" `{}`", short(commit)),
+            _ => String::new(),
+        };
+        let path = self
+            .path
+            .as_ref()
+            .map(|p| format!(" *{p}*"))
+            .unwrap_or_default();
+
+        let patches = match self.patches {
+            Some(0) | None => String::new(),
+            Some(1) => " +1 patch".to_string(),
+            Some(n) => format!(" +{n} patches"),
+        };
+        let worktree = self
+            .worktree
+            .as_ref()
+            .map(Worktree::human)
+            .unwrap_or_default();
+        let upstream = self
+            .upstream
+            .as_ref()
+            .map(Upstream::human)
+            .unwrap_or_default();
+        let operation = self
+            .operation
+            .as_ref()
+            .map(Operation::human)
+            .unwrap_or_default();
+        let error = self
+            .error
+            .as_ref()
+            .map(|e| format!(" ({e})"))
+            .unwrap_or_default();
+
+        format!(
+            "**{}**{reference}: {}{commit}{patches}{path}{worktree}{upstream}{operation}{error}",
+            self.name, self.status
+        )
+    }
+
+    /// Tab separated fields, with extra columns appended for `status`, `outdated` and `log`, and
+    /// the hint last.
+    fn porcelain(&self) -> String {
+        let worktree = self.worktree.iter().flat_map(|tree| {
+            [
+                Some(tree.modified.to_string()),
+                Some(tree.untracked.to_string()),
+                tree.ahead.map(|n| n.to_string()),
+                tree.behind.map(|n| n.to_string()),
+                tree.locked.map(|locked| locked.to_string()),
+                Some(tree.detached.to_string()),
+            ]
+        });
+        let upstream = self.upstream.iter().flat_map(|upstream| {
+            [
+                Some(upstream.latest.clone()),
+                Some(upstream.behind.to_string()),
+                upstream.tag.clone(),
+                Some(upstream.date.clone()),
+            ]
+        });
+        let operation = self.operation.iter().flat_map(|operation| {
+            [
+                Some(operation.id.to_string()),
+                Some(operation.date.clone()),
+                Some(operation.user.clone()),
+                Some(operation.command.clone()),
+            ]
+        });
+
+        [
+            Some(self.status.name().to_string()),
+            Some(self.name.clone()),
+            self.reference.clone(),
+            self.commit.clone(),
+            self.previous.clone(),
+            self.url.clone(),
+            self.path.clone(),
+            self.error.clone(),
+        ]
+        .into_iter()
+        .chain(worktree)
+        .chain(upstream)
+        .chain(operation)
+        .chain([self.hint.clone()])
+        .map(|field| field.map_or("-".to_string(), |f| f.replace(['\t', '\n'], " ")))
+        .collect::<Vec<_>>()
+        .join("\t")
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Report {
+    pub message: String,
+    pub dependencies: Vec<Entry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    /// Set for `--dry-run`, where the report is a plan and nothing was changed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    #[serde(skip)]
+    pub table: bool,
+    /// Non-zero when some dependencies failed but the command carried on
+    #[serde(skip)]
+    pub exit_code: i32,
+}
+
+impl Report {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            dependencies: Vec::new(),
+            snippet: None,
+            summary: None,
+            dry_run: false,
+            table: false,
+            exit_code: 0,
+        }
+    }
+
+    /// Report of what a `--dry-run` would have done.
+    pub fn plan(message: impl Into<String>) -> Self {
+        Self {
+            dry_run: true,
+            ..Self::new(message)
+        }
+    }
+
+    /// Records `entry` as failed with `error`, keeping the exit code of the first failure.
+    pub fn fail(&mut self, entry: Entry, error: &Error) {
+        if self.exit_code == 0 {
+            self.exit_code = error.kind.exit_code();
+        }
+
+        self.dependencies.push(Entry {
+            status: Status::Failed,
+            error: Some(match &error.source {
+                Some(source) => format!("{}: {source}", error.message),
+                None => error.message.clone(),
+            }),
+            hint: error.hint.clone(),
+            ..entry
+        });
+    }
+
+    /// Tallies the dependencies by outcome, for commands that carry on past failures.
+    pub fn summarize(mut self) -> Self {
+        let mut summary = Summary::default();
+        for entry in &self.dependencies {
+            match entry.status {
+                Status::Failed => summary.failed += 1,
+                Status::Present | Status::UpToDate | Status::Pinned | Status::RolledBack => {
+                    summary.unchanged += 1
+                }
+                _ => summary.succeeded += 1,
+            }
+        }
+
+        self.summary = Some(summary);
+        self
+    }
+
+    /// Marks what the command changed as taken back, after a failure rolled everything back.
+    pub fn rolled_back(mut self) -> Self {
+        self.message = "Rolled back, as some dependencies failed".to_string();
+        for entry in &mut self.dependencies {
+            if !matches!(
+                entry.status,
+                Status::Failed | Status::Present | Status::UpToDate | Status::Pinned
+            ) {
+                entry.status = Status::RolledBack;
+                if entry.previous.is_some() {
+                    std::mem::swap(&mut entry.commit, &mut entry.previous);
+                }
+                entry.changes.clear();
+            }
+        }
+
+        match self.summary {
+            Some(_) => self.summarize(),
+            None => self,
+        }
+    }
+
+    /// Markdown listing the commits each moved dependency brought in, for `update --changelog`.
+    pub fn changelog(&self) -> String {
+        let mut text = "# Dependency updates\n".to_string();
+
+        for entry in self.dependencies.iter().filter(|e| !e.changes.is_empty()) {
+            let reference = entry
+                .reference
+                .as_ref()
+                .map(|r| format!(" ({r})"))
+                .unwrap_or_default();
+            let range = match (&entry.previous, &entry.commit) {
+                (Some(old), Some(new)) => format!(" `{}` → `{}`", short(old), short(new)),
+                _ => String::new(),
+            };
+
+            text.push_str(&format!("\n## {}{reference}{range}\n\n", entry.name));
+            for change in &entry.changes {
+                text.push_str(&change.markdown());
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).unwrap(),
+            Format::Porcelain => self
+                .dependencies
+                .iter()
+                .map(Entry::porcelain)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Format::Human if self.table => termimad::term_text(&format!(
+                "\n|**Name**|**Ref**|**Commit**|\n|:-:|:-:|:-:|\n{}\n",
+                self.dependencies
+                    .iter()
+                    .map(|entry| format!(
+                        "| **{}** | {} | *{}* |",
+                        entry.name,
+                        entry.reference.as_deref().unwrap_or("HEAD"),
+                        entry.commit.as_deref().unwrap_or("-")
+                    ))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ))
+            .to_string(),
+            Format::Human => {
+                let mut text = self.dependencies.iter().fold(
+                    termimad::inline(&format!("**{}**", self.message)).to_string(),
+                    |acc, entry| {
+                        let mut line = format!("{acc}\n{}", termimad::inline(&entry.human()));
+                        if let Some(hint) = &entry.hint {
+                            line = format!(
+                                "{line}\n  {}",
+                                termimad::inline(&format!("**Hint**: {hint}"))
+                            );
+                        }
+                        entry.changes.iter().fold(line, |acc, change| {
+                            format!("{acc}\n  {}", termimad::inline(&change.markdown()))
+                        })
+                    },
+                );
+                if let Some(summary) = &self.summary {
+                    text = format!(
+                        "{text}\n{}",
+                        termimad::inline(&format!(
+                            "*{} succeeded, {} failed, {} unchanged*",
+                            summary.succeeded, summary.failed, summary.unchanged
+                        ))
+                    );
+                }
+                if let Some(snippet) = &self.snippet {
+                    text = format!("{text}\n\n{snippet}");
+                }
+                text
+            }
+        }
+    }
+}
+
+pub fn render_error(error: &Error, format: Format) -> String {
+    match format {
+        Format::Json => serde_json::to_string_pretty(&serde_json::json!({
+            "error": {
+                "kind": error.kind.name(),
+                "exit_code": error.kind.exit_code(),
+                "message": error.message,
+                "dependency": error.dependency,
+                "hint": error.hint,
+                "source": error.source.as_ref().map(|s| s.to_string()),
+            }
+        }))
+        .unwrap(),
+        Format::Porcelain => format!(
+            "error\t{}\t{}\t{}",
+            error.kind.name(),
+            error.dependency.as_deref().unwrap_or("-"),
+            error.message
+        ),
+        Format::Human => {
+            let mut erroneous = termimad::MadSkin::default();
+            erroneous
+                .bold
+                .set_fg(termimad::crossterm::style::Color::Red);
+
+            let mut text = erroneous
+                .inline(&format!("**Error**: {}", error.summary()))
+                .to_string();
+            if let Some(source) = &error.source {
+                text = format!("{text}\n{source}");
+            }
+            if let Some(hint) = &error.hint {
+                text = format!("{text}\n{}", termimad::inline(&format!("**Hint**: {hint}")));
+            }
+            text
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_in_utc() {
+        assert_eq!(timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(timestamp(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(timestamp(1_790_000_000), "2026-09-21 14:13 UTC");
+        assert_eq!(timestamp(-60), "1969-12-31 23:59 UTC");
+    }
+
+    #[test]
+    fn worktree_cleanliness() {
+        let clean = Worktree {
+            ahead: Some(0),
+            behind: Some(2),
+            locked: Some(true),
+            ..Default::default()
+        };
+        assert!(clean.is_clean());
+        assert!(Worktree {
+            locked: None,
+            ..clean.clone()
+        }
+        .is_clean());
+        assert!(!Worktree {
+            locked: Some(false),
+            ..clean.clone()
+        }
+        .is_clean());
+        assert!(!Worktree {
+            untracked: 1,
+            ..clean.clone()
+        }
+        .is_clean());
+        assert!(!Worktree {
+            ahead: Some(1),
+            ..clean
+        }
+        .is_clean());
+    }
+
+    #[test]
+    fn short_hashes() {
+        assert_eq!(short("6a14cd2e9f0b1c3d"), "6a14cd2");
+        assert_eq!(short("6a14cd"), "6a14cd");
+        assert_eq!(short(""), "");
+    }
+}
@@ -0,0 +1,487 @@
+use std::path::{Path, PathBuf};
+
+use git2::{Commit, Diff, Email, EmailCreateOptions, Oid, Repository, Signature, Sort, Time};
+
+use crate::{
+    error::{Context, Error, Kind, Result},
+    git, lock,
+    types::{Dependency, LockedDependency, Reference},
+};
+
+pub const DIRECTORY: &str = "patches";
+
+/// Commits vendman makes from patch files are committed as this, so they can be told apart.
+const COMMITTER: (&str, &str) = ("vendman", "vendman@localhost");
+
+pub fn directory(home: &Path, name: &str) -> PathBuf {
+    home.join(DIRECTORY).join(name)
+}
+
+/// The `.patch` files kept for `name`, in the order they apply.
+pub fn series(home: &Path, name: &str) -> Result<Vec<PathBuf>> {
+    let directory = directory(home, name);
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut series = std::fs::read_dir(&directory)
+        .context(Kind::Filesystem, "Can't read patches directory")?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "patch"))
+        .collect::<Vec<_>>();
+    series.sort();
+
+    Ok(series)
+}
+
+fn is_patch(commit: &Commit) -> bool {
+    commit.committer().name() == Some(COMMITTER.0)
+        && commit.committer().email() == Some(COMMITTER.1)
+}
+
+/// How many patch commits sit on HEAD, and the upstream commit below them.
+pub fn applied<'r>(repo: &'r Repository) -> Result<(usize, Commit<'r>)> {
+    let mut count = 0;
+    let mut upstream = git::resolve(repo, "HEAD")?;
+    while is_patch(&upstream) {
+        count += 1;
+        upstream = upstream
+            .parent(0)
+            .context(Kind::Git, "Can't find parent of patch commit")?;
+    }
+
+    Ok((count, upstream))
+}
+
+/// Work in `repo` that only exists locally, leaving out the patch commits the series keeps anyway.
+pub fn local_changes(repo: &Repository) -> Result<Vec<String>> {
+    let (_, base) = applied(repo)?;
+    git::local_changes(repo, base.id())
+}
+
+/// Commits each patch in the series on top of HEAD and checks out the result, returning how many applied.
+///
+/// Nothing moves unless the whole series applies.
+pub fn apply(repo: &Repository, home: &Path, name: &str) -> Result<usize> {
+    let (applied, tip) = stack(repo, home, name, git::resolve(repo, "HEAD")?)?;
+    if applied > 0 {
+        git::checkout(repo, git::tracked_branch(repo).as_deref(), &tip)?;
+    }
+
+    Ok(applied)
+}
+
+/// Commits the series on top of `onto` without checking anything out, returning how many
+/// patches applied and the last commit.
+pub fn stack<'r>(
+    repo: &'r Repository,
+    home: &Path,
+    name: &str,
+    onto: Commit<'r>,
+) -> Result<(usize, Commit<'r>)> {
+    let series = series(home, name)?;
+
+    // Fixed times make applying the same series onto the same commit give the same commits
+    let base = onto.time();
+    let committer = Signature::new(COMMITTER.0, COMMITTER.1, &base)
+        .context(Kind::Git, "Can't create signature")?;
+    let mut tip = onto;
+
+    for path in &series {
+        let file = path.file_name().unwrap_or_default().to_string_lossy();
+        let text = std::fs::read(path).context(Kind::Filesystem, format!("Can't read {file}"))?;
+
+        let diff = Diff::from_buffer(&text).context(Kind::Config, format!("Can't parse {file}"))?;
+        let tree = repo
+            .apply_to_tree(&tip.tree().context(Kind::Git, "Can't read tree")?, &diff, None)
+            .and_then(|mut index| index.write_tree_to(repo))
+            .and_then(|tree| repo.find_tree(tree))
+            .map_err(|e| {
+                Error::new(Kind::Conflict, format!("Patch {file} no longer applies"))
+                    .source(e)
+                    .hint(format!(
+                        "Recreate the commits in the clone with `git am -3`, fix them and run `vendman patch refresh {name}`"
+                    ))
+            })?;
+
+        let text = String::from_utf8_lossy(&text);
+        let (author, message) = header(&text, &file);
+        let time = date(&text).unwrap_or(base);
+        let author = match author {
+            Some((name, email)) => Signature::new(&name, &email, &time),
+            None => Signature::new(COMMITTER.0, COMMITTER.1, &time),
+        }
+        .context(Kind::Git, "Can't create signature")?;
+
+        let commit = repo
+            .commit(None, &author, &committer, &message, &tree, &[&tip])
+            .context(Kind::Git, format!("Can't commit {file}"))?;
+        tip = repo
+            .find_commit(commit)
+            .context(Kind::Git, "Can't find commit")?;
+    }
+
+    Ok((series.len(), tip))
+}
+
+/// Moves HEAD off the commits `apply` made, back to the upstream commit they sit on.
+///
+/// Refuses when commits of the user's own sit on top of the series, as those would be lost.
+pub fn unapply(repo: &Repository, base: Option<Oid>) -> Result<()> {
+    let head = git::resolve(repo, "HEAD")?;
+    let (_, upstream) = applied(repo)?;
+
+    if upstream.id() == head.id() {
+        if let Some(base) = base.filter(|&base| base != head.id() && repo.find_commit(base).is_ok())
+        {
+            let mut walk = repo.revwalk().context(Kind::Git, "Can't walk history")?;
+            walk.push(head.id())
+                .and_then(|_| walk.hide(base))
+                .context(Kind::Git, "Can't walk history")?;
+
+            for oid in walk {
+                let oid = oid.context(Kind::Git, "Can't walk history")?;
+                if repo.find_commit(oid).is_ok_and(|commit| is_patch(&commit)) {
+                    return Err(Error::new(
+                        Kind::Conflict,
+                        "Local commits sit on top of the patch series",
+                    )
+                    .hint("Run `vendman patch refresh` to save them as patches"));
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    git::checkout(repo, git::tracked_branch(repo).as_deref(), &upstream)
+}
+
+/// The upstream commit local work sits on, with the reference to lock it under.
+fn upstream(repo: &Repository, dep: &Dependency) -> Result<(Oid, String)> {
+    match dep.reference() {
+        Reference::Tag(tag) => Ok((
+            git::resolve(repo, &format!("refs/tags/{tag}"))?.id(),
+            tag.to_string(),
+        )),
+        Reference::Rev(rev) => Ok((git::resolve(repo, rev)?.id(), rev.to_string())),
+        Reference::Branch(_) | Reference::Default => {
+            let branch = match dep.reference() {
+                Reference::Branch(branch) => branch.to_string(),
+                _ => git::tracked_branch(repo)
+                    .context(Kind::Git, "HEAD is detached, can't find branch")?,
+            };
+            let tip = git::resolve(repo, &format!("refs/remotes/origin/{branch}"))?;
+            let base = repo.merge_base(git::head_id(repo)?, tip.id()).context(
+                Kind::Git,
+                format!("Can't find where HEAD left origin/{branch}"),
+            )?;
+
+            Ok((base, branch))
+        }
+    }
+}
+
+/// Rewrites the series from the commits between upstream and HEAD, then re-applies it on upstream.
+///
+/// Returns the lock entry for the upstream commit, which moves if local work was rebased.
+pub fn refresh(
+    repo: &Repository,
+    home: &Path,
+    name: &str,
+    dep: &Dependency,
+) -> Result<(LockedDependency, Vec<PathBuf>)> {
+    let (base, reference) = upstream(repo, dep)?;
+
+    let mut walk = repo.revwalk().context(Kind::Git, "Can't walk history")?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)
+        .and_then(|_| walk.push_head())
+        .and_then(|_| walk.hide(base))
+        .context(Kind::Git, "Can't walk history")?;
+    let commits = walk
+        .map(|oid| oid.and_then(|oid| repo.find_commit(oid)))
+        .collect::<Result<Vec<_>, _>>()
+        .context(Kind::Git, "Can't walk history")?;
+
+    if let Some(merge) = commits.iter().find(|commit| commit.parent_count() != 1) {
+        return Err(Error::new(
+            Kind::Conflict,
+            format!("Can't turn merge commit {} into a patch", merge.id()),
+        ));
+    }
+
+    let old = series(home, name)?;
+    if commits.is_empty() && !old.is_empty() {
+        return Err(Error::new(
+            Kind::Conflict,
+            format!(
+                "No commits above upstream, refusing to delete {} patches",
+                old.len()
+            ),
+        )
+        .hint(format!(
+            "Remove {DIRECTORY}/{name} by hand to drop the series"
+        )));
+    }
+
+    let directory = directory(home, name);
+    for old in old {
+        std::fs::remove_file(&old).context(Kind::Filesystem, "Can't remove old patch")?;
+    }
+    std::fs::create_dir_all(&directory)
+        .context(Kind::Filesystem, "Can't create patches directory")?;
+
+    let mut written = Vec::new();
+    for (index, commit) in commits.iter().enumerate() {
+        let parent = commit.parent(0).context(Kind::Git, "Can't find parent")?;
+        let diff = repo
+            .diff_tree_to_tree(
+                Some(&parent.tree().context(Kind::Git, "Can't read tree")?),
+                Some(&commit.tree().context(Kind::Git, "Can't read tree")?),
+                None,
+            )
+            .context(Kind::Git, "Can't diff commit")?;
+        let summary = commit.summary().unwrap_or_default();
+        let email = Email::from_diff(
+            &diff,
+            index + 1,
+            commits.len(),
+            &commit.id(),
+            summary,
+            commit.body().unwrap_or_default(),
+            &commit.author(),
+            &mut EmailCreateOptions::new(),
+        )
+        .context(Kind::Git, "Can't format patch")?;
+
+        let path = directory.join(format!("{:04}-{}.patch", index + 1, slug(summary)));
+        std::fs::write(&path, email.as_slice()).context(Kind::Filesystem, "Can't write patch")?;
+        written.push(path);
+    }
+
+    let base = repo
+        .find_commit(base)
+        .context(Kind::Git, "Can't find locked commit")?;
+    git::checkout(repo, git::tracked_branch(repo).as_deref(), &base)?;
+    let locked = lock::entry(repo, &reference)?;
+    apply(repo, home, name)?;
+
+    Ok((locked, written))
+}
+
+/// Author and commit message from the mail headers `git format-patch` writes.
+fn header(text: &str, file: &str) -> (Option<(String, String)>, String) {
+    // A plain `git diff` has nothing to take a message from
+    if !text.starts_with("From ") {
+        return (None, format!("{}\n", file.trim_end_matches(".patch")));
+    }
+
+    let (headers, rest) = text.split_once("\n\n").unwrap_or((text, ""));
+
+    // Long headers carry on over indented lines
+    let mut fields = Vec::<String>::new();
+    for line in headers.lines() {
+        match (line.starts_with([' ', '\t']), fields.last_mut()) {
+            (true, Some(field)) => field.push_str(line.trim_end()),
+            _ => fields.push(line.trim_end().to_string()),
+        }
+    }
+
+    let mut author = None;
+    let mut subject = String::new();
+    for field in &fields {
+        if let Some(from) = field.strip_prefix("From: ") {
+            if let Some((name, email)) = from.split_once(" <") {
+                author = Some((
+                    name.trim().trim_matches('"').to_string(),
+                    email.trim_end_matches('>').to_string(),
+                ));
+            }
+        } else if let Some(text) = field.strip_prefix("Subject: ") {
+            subject = text.to_string();
+        }
+    }
+
+    // Drop the `[PATCH n/m]` prefix
+    let subject = match subject.strip_prefix('[').and_then(|s| s.split_once("] ")) {
+        Some((_, subject)) => subject.to_string(),
+        None => subject,
+    };
+    let subject = match subject.is_empty() {
+        true => file.trim_end_matches(".patch").to_string(),
+        false => subject,
+    };
+
+    // The body ends where the diffstat starts, right away when there is no body
+    let body = format!("\n{rest}");
+    let body = body.split("\n---\n").next().unwrap_or_default().trim();
+    let message = match body.is_empty() {
+        true => format!("{subject}\n"),
+        false => format!("{subject}\n\n{body}\n"),
+    };
+
+    (author, message)
+}
+
+/// When a `git format-patch` file says it was authored, e.g. `Date: Tue, 3 Mar 2026 10:00:00 +0100`.
+fn date(text: &str) -> Option<Time> {
+    if !text.starts_with("From ") {
+        return None;
+    }
+
+    let (headers, _) = text.split_once("\n\n").unwrap_or((text, ""));
+    let value = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("Date: "))?;
+    // The weekday is optional
+    let value = value.split_once(", ").map_or(value, |(_, rest)| rest);
+
+    let mut parts = value.split_whitespace();
+    let day = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?;
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|m| *m == month)? as i64
+        + 1;
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let clock = parts
+        .next()?
+        .split(':')
+        .map(|part| part.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (hours, minutes, seconds) = match clock[..] {
+        [h, m, s] => (h, m, s),
+        [h, m] => (h, m, 0),
+        _ => return None,
+    };
+    let zone = parts.next()?;
+    let sign = match zone.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let offset =
+        sign * (zone.get(1..3)?.parse::<i32>().ok()? * 60 + zone.get(3..5)?.parse::<i32>().ok()?);
+
+    // Civil date to days, from Howard Hinnant's `days_from_civil`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let local = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Some(Time::new(local - i64::from(offset) * 60, offset))
+}
+
+fn slug(summary: &str) -> String {
+    let slug = summary
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '-',
+        })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    slug.chars().take(52).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "From 6a14cd2e Mon Sep 17 00:00:00 2001
+From: Jane Doe <jane@example.com>
+Date: Tue, 3 Mar 2026 10:00:00 +0100
+Subject: [PATCH 1/2] Bump version
+
+Needed for the registry.
+---
+ Cargo.toml | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+";
+
+    fn author(name: &str, email: &str) -> Option<(String, String)> {
+        Some((name.to_string(), email.to_string()))
+    }
+
+    #[test]
+    fn header_from_format_patch() {
+        assert_eq!(
+            header(PATCH, "0001-bump-version.patch"),
+            (
+                author("Jane Doe", "jane@example.com"),
+                "Bump version\n\nNeeded for the registry.\n".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn header_continuation_lines() {
+        let text = PATCH
+            .replace(
+                "From: Jane Doe <jane@example.com>",
+                "From: \"Doe, Jane\"\n <jane@example.com>",
+            )
+            .replace(
+                "Subject: [PATCH 1/2] Bump version",
+                "Subject: [PATCH 1/2] Bump version for\n the registry",
+            );
+
+        assert_eq!(
+            header(&text, "0001-bump-version.patch"),
+            (
+                author("Doe, Jane", "jane@example.com"),
+                "Bump version for the registry\n\nNeeded for the registry.\n".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn header_without_body() {
+        let text = PATCH.replace("\nNeeded for the registry.\n", "");
+        assert_eq!(header(&text, "x.patch").1, "Bump version\n");
+    }
+
+    #[test]
+    fn header_of_plain_diff() {
+        let text = "diff --git a/Cargo.toml b/Cargo.toml\n--- a/Cargo.toml\n+++ b/Cargo.toml\n";
+        assert_eq!(
+            header(text, "0001-local-fix.patch"),
+            (None, "0001-local-fix\n".to_string())
+        );
+    }
+
+    #[test]
+    fn date_of_format_patch() {
+        let time = date(PATCH).unwrap();
+        assert_eq!((time.seconds(), time.offset_minutes()), (1_772_528_400, 60));
+
+        let time =
+            date(&PATCH.replace("Tue, 3 Mar 2026 10:00:00 +0100", "29 Feb 2000 00:00 -0530"))
+                .unwrap();
+        assert_eq!(
+            (time.seconds(), time.offset_minutes()),
+            (951_782_400 + 19_800, -330)
+        );
+
+        assert!(date(&PATCH.replace("Mar", "March")).is_none());
+        assert!(date("diff --git a/x b/x\n").is_none());
+    }
+
+    #[test]
+    fn slug_of_subject() {
+        assert_eq!(slug("Bump version"), "bump-version");
+        assert_eq!(slug("Fix `parse()` -- again!"), "fix-parse-again");
+        assert_eq!(slug("Ünïcode only"), "n-code-only");
+        assert_eq!(slug(&"long ".repeat(20)).len(), 52);
+        assert_eq!(slug("!!!"), "");
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use git2::{build::CheckoutBuilder, RemoteCallbacks};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Live fetch and checkout progress on stderr, one line per dependency being worked on.
+pub struct Progress {
+    enabled: bool,
+    state: Mutex<State>,
+}
+
+struct State {
+    lines: Vec<(String, String)>,
+    drawn: usize,
+    redrawn: Instant,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            state: Mutex::new(State {
+                lines: Vec::new(),
+                drawn: 0,
+                redrawn: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn line(&self, name: &str) -> Line<'_> {
+        self.line_with(name, "starting")
+    }
+
+    /// A line showing `text` from the start, for waits with nothing else to report.
+    pub fn line_with(&self, name: &str, text: &str) -> Line<'_> {
+        if self.enabled {
+            let mut state = self.state.lock().unwrap();
+            state.lines.push((name.to_string(), text.to_string()));
+            self.draw(&mut state);
+        }
+
+        Line {
+            progress: self,
+            name: name.to_string(),
+        }
+    }
+
+    fn set(&self, name: &str, text: String) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some((_, line)) = state.lines.iter_mut().find(|(n, _)| n == name) {
+            *line = text;
+        }
+        if state.redrawn.elapsed() >= REDRAW_INTERVAL {
+            self.draw(&mut state);
+        }
+    }
+
+    fn remove(&self, name: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.lines.retain(|(n, _)| n != name);
+        self.draw(&mut state);
+    }
+
+    fn draw(&self, state: &mut State) {
+        let width = match termimad::crossterm::terminal::size() {
+            Ok((width, _)) if width > 0 => width as usize,
+            _ => 80,
+        };
+
+        let mut text = String::new();
+        if state.drawn > 0 {
+            // Back to the first line we drew, clearing everything below
+            text.push_str(&format!("\x1b[{}F", state.drawn));
+        }
+        text.push_str("\x1b[J");
+        for (name, line) in &state.lines {
+            let line = format!("{name}: {line}");
+            // Wrapped lines would throw off how far up the next redraw moves
+            text.extend(line.chars().take(width.saturating_sub(1)));
+            text.push('\n');
+        }
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = stderr.write_all(text.as_bytes());
+        let _ = stderr.flush();
+
+        state.drawn = state.lines.len();
+        state.redrawn = Instant::now();
+    }
+}
+
+/// One dependency's line, removed from the display when dropped.
+pub struct Line<'p> {
+    progress: &'p Progress,
+    name: String,
+}
+
+impl Line<'_> {
+    pub fn set(&self, text: impl Into<String>) {
+        self.progress.set(&self.name, text.into());
+    }
+
+    pub fn callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.transfer_progress(|stats| {
+            let text = match stats.received_objects() < stats.total_objects() {
+                true => format!(
+                    "receiving objects {}% ({}/{}), {}",
+                    stats.received_objects() * 100 / stats.total_objects(),
+                    stats.received_objects(),
+                    stats.total_objects(),
+                    size(stats.received_bytes())
+                ),
+                false => format!(
+                    "resolving deltas {}/{}",
+                    stats.indexed_deltas(),
+                    stats.total_deltas()
+                ),
+            };
+            self.set(text);
+            true
+        });
+        callbacks
+    }
+
+    pub fn checkout(&self) -> CheckoutBuilder<'_> {
+        let mut checkout = CheckoutBuilder::new();
+        checkout.progress(|_, done, total| {
+            self.set(format!("checking out files {done}/{total}"));
+        });
+        checkout
+    }
+}
+
+impl Drop for Line<'_> {
+    fn drop(&mut self) {
+        self.progress.remove(&self.name);
+    }
+}
+
+fn size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1 << 20 => format!("{:.2} MiB", b as f64 / (1 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.2} KiB", b as f64 / (1 << 10) as f64),
+        b => format!("{b} B"),
+    }
+}
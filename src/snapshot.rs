@@ -0,0 +1,287 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use git2::{Oid, Repository, RepositoryState};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config,
+    error::{Context, Error, ForDependency, Kind, Result},
+    git, patches, workspace,
+};
+
+/// The state from before the last command `undo` can take back.
+const FILE: &str = "snapshot.toml";
+
+/// The config, lockfile and each clone's HEAD at one point, to go back to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub config: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockfile: Option<String>,
+    #[serde(default)]
+    pub heads: BTreeMap<String, Head>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    pub path: PathBuf,
+    /// Branch HEAD was on, unless detached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Checked out commit, missing when there was no clone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+/// What rolling back did to one clone.
+pub enum Reverted {
+    /// HEAD moved back from the first commit to the second
+    Moved(Oid, Oid),
+    /// The clone wasn't there before, so it was removed
+    Removed(PathBuf),
+    /// The clone was there before but is gone, and needs cloning again
+    Missing,
+    Unchanged,
+}
+
+pub fn take(home: &Path) -> Result<Snapshot> {
+    let config_file = config::read(home)?;
+
+    let mut heads = BTreeMap::new();
+    for (name, dep) in &config_file.dependencies {
+        let path = dep.checkout_path(home, name);
+        let head = match Repository::open(&path) {
+            Ok(repo) => {
+                // A rebase left to resolve stands for where it started, as aborting it goes back there
+                let rebase = repo.open_rebase(None).ok();
+                match rebase
+                    .as_ref()
+                    .and_then(|r| Some((r.orig_head_name()?, r.orig_head_id()?)))
+                {
+                    Some((branch, commit)) => Head {
+                        branch: branch.strip_prefix("refs/heads/").map(|b| b.to_string()),
+                        commit: Some(commit.to_string()),
+                        path,
+                    },
+                    None => Head {
+                        branch: git::tracked_branch(&repo),
+                        commit: Some(git::head_id(&repo).dependency(name)?.to_string()),
+                        path,
+                    },
+                }
+            }
+            Err(_) => Head {
+                path,
+                branch: None,
+                commit: None,
+            },
+        };
+        heads.insert(name.clone(), head);
+    }
+
+    let lockfile = home.join(workspace::LOCKFILE);
+    Ok(Snapshot {
+        config: std::fs::read_to_string(home.join(workspace::CONFIG))
+            .context(Kind::Filesystem, "Can't read config file")?,
+        lockfile: match lockfile.exists() {
+            true => Some(
+                std::fs::read_to_string(lockfile)
+                    .context(Kind::Filesystem, "Can't read lockfile")?,
+            ),
+            false => None,
+        },
+        heads,
+    })
+}
+
+pub fn read(home: &Path) -> Result<Snapshot> {
+    let path = home.join(FILE);
+    if !path.exists() {
+        return Err(Error::new(Kind::Config, "Nothing to undo"));
+    }
+
+    toml::from_str(&std::fs::read_to_string(path).context(Kind::Filesystem, "Can't read snapshot")?)
+        .context(Kind::Config, "Can't parse snapshot")
+}
+
+pub fn save(home: &Path, snapshot: &Snapshot) -> Result<()> {
+    workspace::replace(
+        &home.join(FILE),
+        toml::to_string(snapshot).context(Kind::Config, "Can't serialize snapshot")?,
+    )
+    .context(Kind::Filesystem, "Can't write snapshot")
+}
+
+/// Puts the config, lockfile and clones back as `snapshot` has them, returning what happened to
+/// each clone.
+///
+/// Clones made since are removed, and rebases or merges left half done are aborted, unless
+/// `resumable` asks to leave them for `update` to pick up once their conflicts are resolved.
+/// Nothing changes if a clone to remove has local work, unless `force` is set.
+pub fn rollback(
+    home: &Path,
+    snapshot: &Snapshot,
+    resumable: bool,
+    force: bool,
+) -> Result<Vec<(String, Result<Reverted>)>> {
+    let current = config::read(home)?;
+
+    if !force {
+        let made = current
+            .dependencies
+            .iter()
+            .filter(|(name, _)| !snapshot.heads.contains_key(*name))
+            .map(|(name, dep)| (name, dep.checkout_path(home, name)));
+        let uncloned = snapshot
+            .heads
+            .iter()
+            .filter(|(_, head)| head.commit.is_none())
+            .map(|(name, head)| (name, head.path.clone()));
+        for (name, path) in made.chain(uncloned).filter(|(_, path)| path.exists()) {
+            let repo = Repository::open(&path)
+                .context(Kind::Git, "Can't open repository")
+                .dependency(name)?;
+            let changes = patches::local_changes(&repo).dependency(name)?;
+            if !changes.is_empty() {
+                return Err(Error::new(
+                    Kind::Conflict,
+                    format!("Clone has local changes ({})", changes.join(", ")),
+                )
+                .dependency(name)
+                .hint("Use `--force` to remove it anyway"));
+            }
+        }
+    }
+
+    workspace::replace(&home.join(workspace::CONFIG), &snapshot.config)
+        .context(Kind::Filesystem, "Can't write to config file")?;
+    let lockfile = home.join(workspace::LOCKFILE);
+    match &snapshot.lockfile {
+        Some(text) => workspace::replace(&lockfile, text)
+            .context(Kind::Filesystem, "Can't write to lockfile")?,
+        None if lockfile.exists() => {
+            std::fs::remove_file(lockfile).context(Kind::Filesystem, "Can't remove lockfile")?
+        }
+        None => {}
+    }
+
+    let mut reverted = Vec::new();
+    for (name, dep) in &current.dependencies {
+        let path = dep.checkout_path(home, name);
+        if !snapshot.heads.contains_key(name) && path.exists() {
+            reverted.push((name.clone(), remove(&path).dependency(name)));
+        }
+    }
+    for (name, head) in &snapshot.heads {
+        reverted.push((name.clone(), revert(head, resumable).dependency(name)));
+    }
+
+    Ok(reverted)
+}
+
+fn revert(head: &Head, resumable: bool) -> Result<Reverted> {
+    let Some(commit) = &head.commit else {
+        return match head.path.exists() {
+            true => remove(&head.path),
+            false => Ok(Reverted::Unchanged),
+        };
+    };
+    if !head.path.exists() {
+        return Ok(Reverted::Missing);
+    }
+
+    let repo = Repository::open(&head.path).context(Kind::Git, "Can't open repository")?;
+    if resumable && repo.state() != RepositoryState::Clean {
+        return Ok(Reverted::Unchanged);
+    }
+    git::abort(&repo)?;
+
+    let from = git::head_id(&repo)?;
+    let to = Oid::from_str(commit).context(Kind::Config, "Invalid commit in snapshot")?;
+    if from == to && git::tracked_branch(&repo) == head.branch {
+        return Ok(Reverted::Unchanged);
+    }
+
+    let commit = repo
+        .find_commit(to)
+        .context(Kind::Git, format!("Can't find commit {to}"))?;
+    git::checkout(&repo, head.branch.as_deref(), &commit)?;
+
+    Ok(Reverted::Moved(from, to))
+}
+
+fn remove(path: &Path) -> Result<Reverted> {
+    std::fs::remove_dir_all(path)
+        .context(Kind::Filesystem, format!("Can't remove {}", path.display()))?;
+    Ok(Reverted::Removed(path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+
+    use super::*;
+
+    /// A home with `up3` cloned since `older`, holding a commit and an uncommitted file.
+    fn home(test: &str) -> (PathBuf, Snapshot) {
+        let home = std::env::temp_dir().join(format!("vendman-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&home);
+        std::fs::create_dir_all(&home).unwrap();
+
+        let older = "version = \"0.2.0\"\n";
+        std::fs::write(
+            home.join(workspace::CONFIG),
+            format!("{older}\n[dependencies]\nup3 = {{ url = \"/srv/git/up3\" }}\n"),
+        )
+        .unwrap();
+
+        let repo = Repository::init(home.join("up3")).unwrap();
+        std::fs::write(home.join("up3").join("lib.rs"), "pub fn f() {}\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("lib.rs")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("dev", "dev@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "mine", &tree, &[])
+            .unwrap();
+        std::fs::write(home.join("up3").join("wip.rs"), "").unwrap();
+
+        let snapshot = Snapshot {
+            config: older.to_string(),
+            lockfile: None,
+            heads: BTreeMap::new(),
+        };
+        (home, snapshot)
+    }
+
+    #[test]
+    fn older_state_keeps_dirty_clones() {
+        let (home, older) = home("keeps-dirty");
+
+        let error = rollback(&home, &older, false, false).err().unwrap();
+        assert_eq!(error.kind, Kind::Conflict);
+        assert!(home.join("up3").join("wip.rs").exists());
+        assert!(std::fs::read_to_string(home.join(workspace::CONFIG))
+            .unwrap()
+            .contains("up3"));
+
+        std::fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn forced_older_state_removes_dirty_clones() {
+        let (home, older) = home("forced");
+
+        let reverted = rollback(&home, &older, false, true).unwrap();
+        assert!(matches!(reverted[..], [(_, Ok(Reverted::Removed(_)))]));
+        assert!(!home.join("up3").exists());
+        assert_eq!(
+            std::fs::read_to_string(home.join(workspace::CONFIG)).unwrap(),
+            older.config
+        );
+
+        std::fs::remove_dir_all(home).unwrap();
+    }
+}
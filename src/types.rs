@@ -1,15 +1,106 @@
-use std::collections::HashMap;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum Dependency {
-    Dep(String),
-    DepWithHash(String, String),
+pub const CONFIG_VERSION: &str = "0.2.0";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dependency {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<Strategy>,
+}
+
+/// What a dependency is pinned to, at most one of `branch`, `tag` or `rev`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference<'a> {
+    Default,
+    Branch(&'a str),
+    Tag(&'a str),
+    Rev(&'a str),
+}
+
+impl Dependency {
+    pub fn reference(&self) -> Reference<'_> {
+        match (&self.branch, &self.tag, &self.rev) {
+            (Some(branch), _, _) => Reference::Branch(branch),
+            (_, Some(tag), _) => Reference::Tag(tag),
+            (_, _, Some(rev)) => Reference::Rev(rev),
+            _ => Reference::Default,
+        }
+    }
+
+    /// Where the clone lives, `path` being relative to the vendman home.
+    pub fn checkout_path(&self, home: &Path, name: &str) -> PathBuf {
+        match &self.path {
+            Some(path) => home.join(path),
+            None => home.join(name),
+        }
+    }
+}
+
+/// How to authenticate to one host, from an `[auth."<host>"]` table in the config.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Auth {
+    /// User to log in as when the url doesn't name one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Private key to try before ssh-agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<PathBuf>,
+    /// Environment variable holding an HTTPS access token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Config {
     pub version: String,
-    pub dependencies: HashMap<String, Dependency>,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub auth: BTreeMap<String, Auth>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Lockfile {
+    pub version: String,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, LockedDependency>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LockedDependency {
+    pub url: String,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub commit: String,
+    pub tree: String,
+}
+
+/// How `update` moves a local branch that has commits of its own onto upstream.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Only fast-forward, failing when local commits diverge
+    #[default]
+    FfOnly,
+    /// Drop local commits and take upstream as is
+    Reset,
+    /// Replay local commits on top of upstream
+    Rebase,
+    /// Merge upstream into the local branch
+    Merge,
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs::{File, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Context, Kind, Result},
+    progress::Progress,
+};
+
+pub const DIRECTORY: &str = ".vendman";
+pub const CONFIG: &str = "config.toml";
+pub const LOCKFILE: &str = "vendman.lock";
+/// Held locked by whichever command is changing the vendman home.
+pub const GUARD: &str = ".lock";
+
+/// Finds the closest `.vendman` directory containing a config, walking up from `from`.
+pub fn discover(from: &Path) -> Option<PathBuf> {
+    from.ancestors()
+        .map(|dir| dir.join(DIRECTORY))
+        .find(|dir| dir.join(CONFIG).is_file())
+}
+
+pub fn global() -> Result<PathBuf> {
+    Ok(dirs::home_dir()
+        .context(Kind::Filesystem, "Can't find home directory")?
+        .join(DIRECTORY))
+}
+
+/// Resolves the vendman home: explicit override, then project-local, then global.
+pub fn resolve(home: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(home) = home {
+        return Ok(home);
+    }
+
+    let cwd = std::env::current_dir().context(Kind::Filesystem, "Can't read current directory")?;
+    match discover(&cwd) {
+        Some(home) => Ok(home),
+        None => global(),
+    }
+}
+
+/// Takes the advisory lock on `home`, waiting for any other vendman command holding it.
+///
+/// The lock is released when the returned file is dropped.
+pub fn lock(home: &Path, progress: &Progress) -> Result<File> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(home.join(GUARD))
+        .context(Kind::Filesystem, "Can't open lock file")?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let _line = progress.line_with(DIRECTORY, "waiting for another vendman command");
+            file.lock()
+                .context(Kind::Filesystem, "Can't lock .vendman directory")?;
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(e).context(Kind::Filesystem, "Can't lock .vendman directory")
+        }
+    }
+
+    Ok(file)
+}
+
+/// Replaces `path` with `contents` by writing a temporary file beside it and renaming it over,
+/// so a crash leaves either the old file or the new one.
+pub fn replace(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let temporary = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&temporary, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temporary);
+    })
+}